@@ -6,7 +6,7 @@ use websocket_codec::{MessageCodec, Result};
 
 fn run(data: &[u8]) -> Result<()> {
     let mut data = BytesMut::from(data);
-    MessageCodec::client().decode(&mut data)?;
+    MessageCodec::with_masked_encode(true).decode(&mut data)?;
     Ok(())
 }
 
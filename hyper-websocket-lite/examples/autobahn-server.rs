@@ -1,44 +1,28 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::let_underscore_drop)]
 
+use std::error::Error;
+
 use futures_util::{SinkExt, StreamExt};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use hyper_websocket_lite::{server_upgrade, AsyncClient};
-use websocket_codec::{Message, Opcode, Result};
-
-async fn on_client(mut stream_mut: AsyncClient) {
-    let mut stream = loop {
-        let (msg, mut stream) = stream_mut.into_future().await;
-
-        let msg = match msg {
-            Some(Ok(msg)) => msg,
-            Some(Err(_err)) => {
-                let _ = stream.send(Message::close()).await;
-                break stream;
-            }
-            None => {
-                break stream;
-            }
-        };
-
-        let _ = match msg.opcode() {
-            Opcode::Text | Opcode::Binary => stream.send(msg).await,
-            Opcode::Ping => stream.send(Message::pong(msg.into_data())).await,
-            Opcode::Close => {
-                break stream;
-            }
-            Opcode::Pong => Ok(()),
-        };
-
-        stream_mut = stream;
-    };
-
-    let _ = stream.send(Message::close()).await;
+use websocket_lite::ManagedClient;
+
+async fn on_client(client: AsyncClient) {
+    // The managed client answers pings and closes, so only text and binary messages arrive here
+    let mut client = ManagedClient::new(client);
+    while let Some(Ok(msg)) = client.next().await {
+        if client.send(msg).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = client.close().await;
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let addr = ([0, 0, 0, 0], 9001).into();
 
     let make_service =
@@ -2,12 +2,13 @@
 #![allow(clippy::let_underscore_drop)]
 
 use std::env;
+use std::error::Error;
 
 use futures_util::SinkExt;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use hyper_websocket_lite::{server_upgrade, AsyncClient};
-use websocket_codec::{Message, Result};
+use websocket_codec::Message;
 
 async fn on_client(mut client: AsyncClient) {
     let _ = client.send(Message::text("Hello, world!")).await;
@@ -15,7 +16,7 @@ async fn on_client(mut client: AsyncClient) {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let port = env::args().nth(1).unwrap_or_else(|| "9001".to_owned()).parse()?;
     let addr = ([0, 0, 0, 0], port).into();
 
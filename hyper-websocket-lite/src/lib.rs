@@ -5,18 +5,39 @@
 
 use std::future::Future;
 
-use hyper::header::HeaderValue;
+use hyper::header::{HeaderName, HeaderValue};
 use hyper::upgrade::Upgraded;
 use hyper::{header, Body, Request, Response, StatusCode};
 use tokio::task;
 use tokio_util::codec::{Decoder, Framed};
 use websocket_codec::{ClientRequest, MessageCodec};
 
-pub use websocket_codec::Result;
+#[cfg(feature = "deflate")]
+pub use websocket_codec::DeflateConfig;
+pub use websocket_codec::{Error, HandshakeError, ProtocolError, Result};
 
 /// Exposes a `Sink` and a `Stream` for sending and receiving WebSocket messages asynchronously.
 pub type AsyncClient = Framed<Upgraded, MessageCodec>;
 
+/// Exposes a `Sink` that sends WebSocket messages with vectored writes, and a `Stream` for receiving them.
+///
+/// The payload of each message is written from its original [`Bytes`](websocket_lite::Message::data) buffer instead
+/// of being copied into a write buffer, which suits servers that send the same large message to many clients. Convert
+/// an [`AsyncClient`] into a `VectoredClient` with `VectoredClient::new(client)`.
+///
+/// Requires the `websocket-lite` feature.
+#[cfg(feature = "websocket-lite")]
+pub type VectoredClient = websocket_lite::VectoredClient<Upgraded>;
+
+/// Exposes a `Sink` and a `Stream` that answer pings and close frames automatically.
+///
+/// The stream yields only text and binary messages, and ends once the closing handshake is complete. Convert an
+/// [`AsyncClient`] into a `ManagedClient` with `ManagedClient::new(client)`.
+///
+/// Requires the `websocket-lite` feature.
+#[cfg(feature = "websocket-lite")]
+pub type ManagedClient = websocket_lite::ManagedClient<Upgraded>;
+
 /// Accepts a client's WebSocket Upgrade request.
 ///
 /// # Errors
@@ -27,33 +48,165 @@ where
     OnClient: FnOnce(AsyncClient) -> F + Send + 'static,
     F: Future<Output = ()> + Send,
 {
-    let mut response = Response::new(Body::empty());
+    server_upgrade_with_codec(request, MessageCodec::server(), on_client).await
+}
+
+/// Accepts a client's WebSocket Upgrade request, using `codec` to send and receive WebSocket messages.
+///
+/// Use this function instead of [`server_upgrade`] to apply size limits to the messages received from the client,
+/// for instance with [`MessageCodec::with_max_message_size`].
+///
+/// # Errors
+///
+/// This method fails when a header required for the WebSocket protocol is missing in the request.
+pub async fn server_upgrade_with_codec<OnClient, F>(
+    request: Request<Body>,
+    codec: MessageCodec,
+    on_client: OnClient,
+) -> Result<Response<Body>>
+where
+    OnClient: FnOnce(AsyncClient) -> F + Send + 'static,
+    F: Future<Output = ()> + Send,
+{
+    let ws_accept = if let Some(ws_accept) = ws_accept(&request) {
+        ws_accept
+    } else {
+        return Ok(bad_request());
+    };
+
+    spawn_client(request, codec, on_client);
+    switching_protocols(&ws_accept, None)
+}
+
+/// Accepts a client's WebSocket Upgrade request, and agrees to use the permessage-deflate compression extension if
+/// the client offers it.
+///
+/// The `config` parameter holds the server's preferred compression parameters. Decompressed messages from the client
+/// are limited to the size set by [`DeflateConfig::with_max_decompressed_size`], which is 64 MiB by default, so that
+/// a small compressed message cannot expand into a very large one.
+///
+/// # Errors
+///
+/// This method fails when a header required for the WebSocket protocol is missing in the request.
+#[cfg(feature = "deflate")]
+pub async fn server_upgrade_with_deflate<OnClient, F>(
+    request: Request<Body>,
+    codec: MessageCodec,
+    config: &DeflateConfig,
+    on_client: OnClient,
+) -> Result<Response<Body>>
+where
+    OnClient: FnOnce(AsyncClient) -> F + Send + 'static,
+    F: Future<Output = ()> + Send,
+{
+    let ws_accept = if let Some(ws_accept) = ws_accept(&request) {
+        ws_accept
+    } else {
+        return Ok(bad_request());
+    };
 
-    let ws_accept = if let Ok(req) = ClientRequest::parse(|name| {
-        let h = request.headers().get(name)?;
-        h.to_str().ok()
-    }) {
-        req.ws_accept()
+    let offers = joined_header(&request, &header::SEC_WEBSOCKET_EXTENSIONS).unwrap_or_default();
+    let (codec, extensions) = if let Some((agreed, response)) = config.accept_offer(&offers) {
+        (codec.with_deflate(agreed), Some(response))
     } else {
-        *response.status_mut() = StatusCode::BAD_REQUEST;
-        return Ok(response);
+        (codec, None)
     };
 
+    spawn_client(request, codec, on_client);
+    switching_protocols(&ws_accept, extensions.as_deref())
+}
+
+// Joins the values of all headers with the given name, separated by commas
+fn joined_header(request: &Request<Body>, name: &HeaderName) -> Option<String> {
+    let mut values = request.headers().get_all(name).iter().filter_map(|h| h.to_str().ok());
+    let first = values.next()?;
+    Some(values.fold(first.to_owned(), |joined, value| joined + ", " + value))
+}
+
+fn ws_accept(request: &Request<Body>) -> Option<String> {
+    // Headers that hold lists can be split over several lines
+    let headers = request
+        .headers()
+        .keys()
+        .map(|name| (name, joined_header(request, name)))
+        .collect::<Vec<_>>();
+
+    let req = ClientRequest::parse(|name| {
+        let (_, value) = headers.iter().find(|(h, _)| h.as_str().eq_ignore_ascii_case(name))?;
+        value.as_deref()
+    })
+    .ok()?;
+
+    Some(req.ws_accept())
+}
+
+fn bad_request() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    response
+}
+
+fn spawn_client<OnClient, F>(request: Request<Body>, codec: MessageCodec, on_client: OnClient)
+where
+    OnClient: FnOnce(AsyncClient) -> F + Send + 'static,
+    F: Future<Output = ()> + Send,
+{
     task::spawn(async move {
         match hyper::upgrade::on(request).await {
             Ok(upgraded) => {
-                let client = MessageCodec::server().framed(upgraded);
+                let client = codec.framed(upgraded);
                 on_client(client).await;
             }
             Err(e) => eprintln!("upgrade error: {}", e),
         }
     });
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value)
+        .map_err(|e| HandshakeError::new(format!("invalid header value {:?}: {}", value, e)).into())
+}
 
+fn switching_protocols(ws_accept: &str, extensions: Option<&str>) -> Result<Response<Body>> {
+    let mut response = Response::new(Body::empty());
     *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
 
     let headers = response.headers_mut();
     headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
     headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
-    headers.insert(header::SEC_WEBSOCKET_ACCEPT, HeaderValue::from_str(&ws_accept)?);
+    headers.insert(header::SEC_WEBSOCKET_ACCEPT, header_value(ws_accept)?);
+    if let Some(extensions) = extensions {
+        headers.insert(header::SEC_WEBSOCKET_EXTENSIONS, header_value(extensions)?);
+    }
+
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Request};
+
+    use crate::ws_accept;
+
+    #[test]
+    fn joins_list_headers() {
+        let request = |connection: &[&str]| {
+            let mut builder = Request::get("/")
+                .header("Upgrade", "websocket")
+                .header("Sec-WebSocket-Version", "13")
+                .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+
+            for &value in connection {
+                builder = builder.header("Connection", value);
+            }
+
+            builder.body(Body::empty()).unwrap()
+        };
+
+        assert_eq!(
+            ws_accept(&request(&["keep-alive", "Upgrade"])).as_deref(),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(ws_accept(&request(&["keep-alive"])), None);
+    }
+}
@@ -0,0 +1,91 @@
+#![warn(clippy::pedantic)]
+#![cfg_attr(not(test), no_std)]
+
+//! Checks that websocket-codec builds on `core` and `alloc`, without the standard library.
+//!
+//! This crate depends on websocket-codec with `default-features = false`. Build it on its own, with
+//! `cargo build -p no-std-test --target thumbv7em-none-eabi`, so that no other package in the workspace turns on
+//! websocket-codec's `std` feature.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use bytes::BytesMut;
+use websocket_codec::protocol::{Frame, FrameCodec, FrameHeader, Mask};
+use websocket_codec::{Message, MessageCodec, Result};
+
+/// Encodes `messages` as masked frames, as a client would send them.
+///
+/// # Errors
+///
+/// This function fails if a message is too long to encode.
+pub fn encode_as_client(messages: &[Message], mask: u32) -> Result<BytesMut> {
+    let mut codec = FrameCodec::new().with_unmasking(true);
+    let mut dst = BytesMut::new();
+    for message in messages {
+        let header = FrameHeader::new(
+            true,
+            0,
+            message.opcode().into(),
+            Some(Mask::from(mask)),
+            message.data().len().into(),
+        );
+
+        codec.encode_frame(
+            &Frame {
+                header,
+                payload: message.data().clone(),
+            },
+            &mut dst,
+        )?;
+    }
+
+    Ok(dst)
+}
+
+/// Decodes every message in `src`, as a server would receive them.
+///
+/// # Errors
+///
+/// This function fails if the data in `src` breaks the WebSocket protocol.
+pub fn decode_as_server(src: &mut BytesMut) -> Result<Vec<Message>> {
+    let mut codec = MessageCodec::server();
+    let mut messages = Vec::new();
+    while let Some(message) = codec.decode_message(src)? {
+        messages.push(message);
+    }
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use websocket_codec::{CloseCode, Message, MessageCodec};
+
+    use crate::{decode_as_server, encode_as_client};
+
+    #[test]
+    fn round_trips_masked_messages() {
+        let messages = vec![
+            Message::text("Hello"),
+            Message::binary(&b"world"[..]),
+            Message::close_with_reason(CloseCode::Away, "bye".to_owned()),
+        ];
+
+        let mut src = encode_as_client(&messages, 0x1234_5678).unwrap();
+        assert_eq!(decode_as_server(&mut src).unwrap(), messages);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn rejects_unmasked_messages() {
+        let mut src = bytes::BytesMut::new();
+        MessageCodec::server()
+            .encode_message(&Message::text("Hello"), &mut src)
+            .unwrap();
+
+        let err = decode_as_server(&mut src).unwrap_err();
+        assert_eq!(err.close_code(), Some(CloseCode::Protocol));
+    }
+}
@@ -2,11 +2,11 @@
 
 use bytes::BytesMut;
 use criterion::measurement::Measurement;
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
 use static_assertions::const_assert_eq;
 use tokio_util::codec::{Decoder, Encoder};
 use websocket_codec::protocol::FrameHeaderCodec;
-use websocket_codec::MessageCodec;
+use websocket_codec::{Message, MessageCodec};
 
 fn bench_decode<M: Measurement, C, Item>(c: &mut BenchmarkGroup<M>, id: &str, data: &[u8], mut codec: C)
 where
@@ -117,5 +117,96 @@ fn decode_benchmark(c: &mut Criterion) {
     c.finish();
 }
 
-criterion_group!(benches, encode_benchmark, decode_benchmark);
+// Returns a JSON text message of roughly `len` bytes, with some non-ASCII characters
+fn json_text(len: usize) -> String {
+    const RECORD: &str = "{\"id\":12345,\"city\":\"Z\u{fc}rich\",\"price\":\"\u{20ac}9.99\",\"ok\":true},";
+    format!("[{}]", RECORD.repeat(len / RECORD.len()))
+}
+
+fn text_frame(text: &str) -> Vec<u8> {
+    let mut frame = BytesMut::new();
+    MessageCodec::server()
+        .encode(Message::text(text.to_owned()), &mut frame)
+        .unwrap_or_else(|_| panic!("didn't expect encode() to return an error"));
+
+    frame.to_vec()
+}
+
+fn bench_validate<M: Measurement>(c: &mut BenchmarkGroup<M>, id: &str, text: &str) {
+    let data = text.as_bytes();
+    c.throughput(Throughput::Bytes(data.len() as u64));
+    c.bench_function(&format!("std {}", id), |b| {
+        b.iter(|| std::str::from_utf8(black_box(data)).is_ok());
+    });
+    c.bench_function(&format!("simdutf8 {}", id), |b| {
+        b.iter(|| simdutf8::basic::from_utf8(black_box(data)).is_ok());
+    });
+}
+
+// The `simd-utf8` feature validates text with simdutf8 instead of the standard library. The "validate" benchmarks
+// compare the two directly; the "decode text" benchmarks use simdutf8 only when run with `--features simd-utf8`
+fn text_benchmark(c: &mut Criterion) {
+    let small_text = json_text(120);
+    let large_text = json_text(64 * 1024);
+
+    let mut group = c.benchmark_group("validate text");
+    bench_validate(&mut group, "small", &small_text);
+    bench_validate(&mut group, "large", &large_text);
+    group.finish();
+
+    let codec = MessageCodec::with_masked_encode(false);
+    let mut c = c.benchmark_group("decode text");
+    bench_decode(&mut c, "text small", &text_frame(&small_text), codec.clone());
+    bench_decode(&mut c, "text large", &text_frame(&large_text), codec);
+    c.finish();
+}
+
+// Masking dominates the time taken to encode and decode large binary messages: the client masks by copying the payload
+// into the output buffer, and the server unmasks the payload in place
+fn mask_benchmark(c: &mut Criterion) {
+    let masked_codec = MessageCodec::with_masked_encode(true);
+    let mut c = c.benchmark_group("mask");
+
+    for &len in &[16, 256, 4 * 1024, 64 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+        let mut frame = BytesMut::new();
+        masked_codec
+            .clone()
+            .encode(Message::binary(vec![0x55; len]), &mut frame)
+            .unwrap_or_else(|_| panic!("didn't expect encode() to return an error"));
+
+        c.throughput(Throughput::Bytes(len as u64));
+        bench_encode(&mut c, &format!("mask {}", len), &frame, masked_codec.clone());
+        bench_decode(&mut c, &format!("unmask {}", len), &frame, masked_codec.clone());
+    }
+
+    c.finish();
+}
+
+// Some servers split large messages into many small fragments, which the decoder joins back together
+fn reassembly_benchmark(c: &mut Criterion) {
+    const LEN: usize = 10 * 1024 * 1024;
+    const FRAGMENT_LEN: usize = 4 * 1024;
+
+    let codec = MessageCodec::with_masked_encode(false).with_max_outgoing_frame_size(FRAGMENT_LEN);
+    let mut frames = BytesMut::new();
+    codec
+        .clone()
+        .encode(Message::binary(vec![0x55; LEN]), &mut frames)
+        .unwrap_or_else(|_| panic!("didn't expect encode() to return an error"));
+
+    let mut c = c.benchmark_group("reassemble");
+    c.sample_size(10);
+    c.throughput(Throughput::Bytes(LEN as u64));
+    bench_decode(&mut c, "10 MiB in 4 KiB fragments", &frames, codec);
+    c.finish();
+}
+
+criterion_group!(
+    benches,
+    encode_benchmark,
+    decode_benchmark,
+    text_benchmark,
+    mask_benchmark,
+    reassembly_benchmark
+);
 criterion_main!(benches);
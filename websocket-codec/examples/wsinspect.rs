@@ -9,7 +9,7 @@ use bytes::{Buf, BytesMut};
 use structopt::StructOpt;
 use tokio_util::codec::Decoder;
 use websocket_codec::protocol::{DataLength, FrameHeader, FrameHeaderCodec};
-use websocket_codec::{Opcode, Result};
+use websocket_codec::{Opcode, ProtocolError, Result};
 
 fn decode_stream<S: BufRead, C: Decoder>(codec: &mut C, mut stream: S) -> result::Result<Option<C::Item>, C::Error> {
     let mut prev_buf_len = 0;
@@ -100,17 +100,22 @@ fn inspect(path: &Path, dump_header: bool, dump_data: bool) -> Result<()> {
         };
 
         if actual_data_len != data_len {
-            return Err(format!(
+            return Err(ProtocolError::new(format!(
                 "stream contains incomplete data: expected {0} bytes (0x{0:x} bytes), got {1} bytes (0x{1:x} bytes)",
                 data_len, actual_data_len
-            )
+            ))
             .into());
         }
     }
 
     let buf = stream.fill_buf()?;
     if !buf.is_empty() {
-        return Err(format!("additional {} data bytes at end of stream: {:?}", buf.len(), buf).into());
+        return Err(ProtocolError::new(format!(
+            "additional {} data bytes at end of stream: {:?}",
+            buf.len(),
+            buf
+        ))
+        .into());
     }
 
     Ok(())
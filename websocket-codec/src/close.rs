@@ -1,6 +1,6 @@
-use std::{fmt, str};
+use core::fmt;
 
-use bytes::Bytes;
+use crate::utf8::Utf8Bytes;
 
 /// Status code used to indicate why an endpoint is closing the WebSocket connection.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -89,7 +89,7 @@ impl CloseCode {
 impl fmt::Display for CloseCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let code: u16 = self.into();
-        f.write_str(&code.to_string())
+        fmt::Display::fmt(&code, f)
     }
 }
 
@@ -150,7 +150,7 @@ impl From<u16> for CloseCode {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CloseFrame {
     pub(crate) code: CloseCode,
-    pub(crate) reason: Bytes,
+    pub(crate) reason: Utf8Bytes,
 }
 
 impl CloseFrame {
@@ -161,6 +161,6 @@ impl CloseFrame {
 
     /// Returns the reason as text string.
     pub fn reason(&self) -> &str {
-        unsafe { str::from_utf8_unchecked(&self.reason) }
+        &self.reason
     }
 }
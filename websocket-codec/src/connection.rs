@@ -0,0 +1,266 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use bytes::Bytes;
+
+use crate::close::{CloseCode, CloseFrame};
+use crate::message::Message;
+use crate::opcode::Opcode;
+use crate::{Error, ProtocolError, Result};
+
+/// Describes how far a [`Connection`] has got through the closing handshake.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    /// Messages can be sent and received.
+    Open,
+    /// One endpoint has sent a close frame, and the connection is waiting for the other endpoint's close frame.
+    Closing,
+    /// Both endpoints have sent close frames, or the connection failed. The underlying stream can be closed.
+    Closed,
+}
+
+/// Runtime-agnostic state machine that handles WebSocket control frames on behalf of an application.
+///
+/// A `Connection` does no I/O of its own. Pass each message received from the peer to [`Connection::receive`], which
+/// returns only text and binary messages; pass each message the application wants to send to
+/// [`Connection::send`]; and after each of these calls, send the messages returned by [`Connection::next_reply`].
+///
+/// The connection replies to pings with pongs, coalescing pings that arrive before the reply is sent, and echoes the
+/// peer's close frame with the same close code. It rejects data received after a close frame, and close frames with
+/// codes that are not allowed on the wire.
+#[derive(Clone, Debug, Default)]
+pub struct Connection {
+    close_sent: bool,
+    close_received: bool,
+    failed: bool,
+    pending_pong: Option<Bytes>,
+    pending_close: Option<Message>,
+    peer_close: Option<CloseFrame>,
+}
+
+impl Connection {
+    /// Returns a `Connection` in the [`ConnectionState::Open`] state.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how far the connection has got through the closing handshake.
+    #[must_use]
+    pub fn state(&self) -> ConnectionState {
+        if self.pending_close.is_none() && (self.failed || (self.close_sent && self.close_received)) {
+            ConnectionState::Closed
+        } else if self.close_sent || self.close_received || self.failed {
+            ConnectionState::Closing
+        } else {
+            ConnectionState::Open
+        }
+    }
+
+    /// Returns the code and reason from the peer's close frame, if the peer has sent one with a code.
+    #[must_use]
+    pub fn peer_close(&self) -> Option<&CloseFrame> {
+        self.peer_close.as_ref()
+    }
+
+    /// Handles a message received from the peer.
+    ///
+    /// Returns text and binary messages, and messages with any reserved opcodes allowed by the codec, for the
+    /// application to handle. Pings, pongs and close frames are handled by the connection, and return `None`.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the message arrives after the peer's close frame, or if it is a close frame with a
+    /// code that is not allowed. The connection then queues a close frame with [`CloseCode::Protocol`].
+    pub fn receive(&mut self, message: Message) -> Result<Option<Message>> {
+        if self.close_received || self.failed {
+            return self.fail_with(ProtocolError::new("received a message after the close frame"));
+        }
+
+        match message.opcode() {
+            Opcode::Ping => {
+                // Only the most recent ping needs a reply
+                if !self.closing() {
+                    self.pending_pong = Some(message.into_data());
+                }
+
+                Ok(None)
+            }
+            Opcode::Pong => Ok(None),
+            Opcode::Close => {
+                self.close_received = true;
+
+                let reply = if let Some(frame) = message.as_close() {
+                    let code = frame.code();
+                    if !code.is_allowed() {
+                        return self.fail_with(ProtocolError::new(format!("close code {} is not allowed", code)));
+                    }
+
+                    self.peer_close = Some(frame);
+                    Message::close_with_reason(code, String::new())
+                } else {
+                    Message::close()
+                };
+
+                if !self.closing() {
+                    self.pending_close = Some(reply);
+                }
+
+                Ok(None)
+            }
+            _ => Ok(Some(message)),
+        }
+    }
+
+    /// Checks that the application can send a message, and notes when the message is a close frame.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if a close frame has already been sent, or if the connection has failed.
+    pub fn send(&mut self, message: &Message) -> Result<()> {
+        if self.closing() || self.failed {
+            return Err(ProtocolError::new("can't send a message after the close frame").into());
+        }
+
+        if message.opcode() == Opcode::Close {
+            self.close_sent = true;
+            self.pending_pong = None;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the closing handshake, if it hasn't started already, by queuing a close frame.
+    pub fn close(&mut self, code: CloseCode, reason: String) {
+        if !self.closing() {
+            self.pending_pong = None;
+            self.pending_close = Some(Message::close_with_reason(code, reason));
+        }
+    }
+
+    /// Fails the connection because of `error`, queuing a close frame with the error's close code.
+    ///
+    /// Call this when the codec returns an error. No more messages can be sent or received afterwards.
+    pub fn fail(&mut self, error: &Error) {
+        if let Some(code) = error.close_code() {
+            self.close(code, error.to_string());
+        }
+
+        self.failed = true;
+    }
+
+    /// Returns `true` if there is a reply waiting to be sent.
+    #[must_use]
+    pub fn has_reply(&self) -> bool {
+        self.pending_pong.is_some() || self.pending_close.is_some()
+    }
+
+    /// Returns the next pong or close frame that the connection needs to send.
+    pub fn next_reply(&mut self) -> Option<Message> {
+        if let Some(data) = self.pending_pong.take() {
+            return Some(Message::pong(data));
+        }
+
+        let close = self.pending_close.take()?;
+        self.close_sent = true;
+        Some(close)
+    }
+
+    // Returns true once a close frame has been sent or queued
+    fn closing(&self) -> bool {
+        self.close_sent || self.pending_close.is_some()
+    }
+
+    fn fail_with<T>(&mut self, error: ProtocolError) -> Result<T> {
+        let error = Error::from(error);
+        self.fail(&error);
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::close::CloseCode;
+    use crate::connection::{Connection, ConnectionState};
+    use crate::message::Message;
+    use crate::opcode::Opcode;
+
+    fn replies(connection: &mut Connection) -> Vec<Message> {
+        let mut replies = Vec::new();
+        while let Some(reply) = connection.next_reply() {
+            replies.push(reply);
+        }
+
+        replies
+    }
+
+    #[test]
+    fn handles_control_frames() {
+        let mut connection = Connection::new();
+        assert_eq!(
+            connection.receive(Message::text("hello")).unwrap(),
+            Some(Message::text("hello"))
+        );
+        assert_eq!(connection.receive(Message::ping("1")).unwrap(), None);
+        assert_eq!(connection.receive(Message::ping("2")).unwrap(), None);
+        assert_eq!(connection.receive(Message::pong("3")).unwrap(), None);
+        assert_eq!(replies(&mut connection), vec![Message::pong("2")]);
+        assert_eq!(connection.state(), ConnectionState::Open);
+
+        let close = Message::close_with_reason(CloseCode::Away, "bye".to_owned());
+        assert_eq!(connection.receive(close).unwrap(), None);
+        assert_eq!(connection.peer_close().unwrap().reason(), "bye");
+        assert_eq!(connection.state(), ConnectionState::Closing);
+        assert_eq!(
+            replies(&mut connection),
+            vec![Message::close_with_reason(CloseCode::Away, String::new())]
+        );
+        assert_eq!(connection.state(), ConnectionState::Closed);
+
+        connection
+            .receive(Message::text("late"))
+            .expect_err("expected connection to reject data after the close frame");
+    }
+
+    #[test]
+    fn completes_closing_handshake_started_locally() {
+        let mut connection = Connection::new();
+        connection.send(&Message::text("hello")).unwrap();
+        connection.close(CloseCode::Normal, String::new());
+        assert_eq!(
+            replies(&mut connection),
+            vec![Message::close_with_reason(CloseCode::Normal, String::new())]
+        );
+
+        connection
+            .send(&Message::text("late"))
+            .expect_err("expected connection to reject data after the close frame");
+
+        // The peer can still send data until it sees our close frame, but pings no longer get a reply
+        assert_eq!(
+            connection.receive(Message::binary(&b"data"[..])).unwrap(),
+            Some(Message::binary(&b"data"[..]))
+        );
+        assert_eq!(connection.receive(Message::ping("ping")).unwrap(), None);
+        assert_eq!(connection.state(), ConnectionState::Closing);
+
+        assert_eq!(connection.receive(Message::close()).unwrap(), None);
+        assert_eq!(replies(&mut connection), vec![]);
+        assert_eq!(connection.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn rejects_close_codes_that_are_not_allowed() {
+        let mut connection = Connection::new();
+        let close = Message::new(Opcode::Close, &[0x03, 0xed][..]).unwrap(); // 1005
+        let err = connection
+            .receive(close)
+            .expect_err("expected connection to reject close code 1005");
+
+        assert_eq!(err.close_code(), Some(CloseCode::Protocol));
+        let replies = replies(&mut connection);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].as_close().unwrap().code(), CloseCode::Protocol);
+        assert_eq!(connection.state(), ConnectionState::Closed);
+    }
+}
@@ -0,0 +1,711 @@
+use std::fmt::Write;
+use std::io;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::header::{self, ExtensionHeader};
+use crate::limit::{SizeLimit, SizeLimitError};
+use crate::{HandshakeError, ProtocolError, Result};
+
+pub(crate) const EXTENSION_NAME: &str = "permessage-deflate";
+
+// Removed from the end of each compressed message by the sender, and restored by the receiver.
+// See RFC 7692 section 7.2.1.
+const TRAILER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+// The default limit on the length of a decompressed message, in bytes
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Parameters for the permessage-deflate WebSocket extension, as defined in
+/// [RFC 7692](https://tools.ietf.org/html/rfc7692).
+///
+/// Before the handshake, a `DeflateConfig` describes the parameters that an endpoint would like to use. The
+/// handshake produces a second `DeflateConfig` holding the parameters agreed by both endpoints, which is
+/// passed to [`MessageCodec::with_deflate`](crate::MessageCodec::with_deflate).
+///
+/// Because a small compressed message can expand into a very large one, decompressed messages are limited to 64 MiB
+/// by default. Change this limit with [`DeflateConfig::with_max_decompressed_size`]. A lower limit set with
+/// [`MessageCodec::with_max_message_size`](crate::MessageCodec::with_max_message_size) also applies to decompressed
+/// messages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeflateConfig {
+    client_max_window_bits: u8,
+    server_max_window_bits: u8,
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    compression_threshold: usize,
+    compression_level: u32,
+    max_decompressed_size: usize,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            client_max_window_bits: 15,
+            server_max_window_bits: 15,
+            client_no_context_takeover: false,
+            server_no_context_takeover: false,
+            compression_threshold: 64,
+            compression_level: 6,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+}
+
+fn check_window_bits(bits: u8) {
+    assert!(
+        (9..=15).contains(&bits),
+        "window bits must be between 9 and 15, not {}",
+        bits
+    );
+}
+
+fn parse_window_bits(name: &str, value: Option<&str>) -> Result<u8> {
+    let value = value.ok_or_else(|| HandshakeError::new(format!("{} parameter requires a value", name)))?;
+    match value.parse() {
+        Ok(bits) if (8..=15).contains(&bits) => Ok(bits),
+        _ => Err(HandshakeError::new(format!("{} parameter must be between 8 and 15, not {:?}", name, value)).into()),
+    }
+}
+
+fn check_no_value(name: &str, value: Option<&str>) -> Result<()> {
+    if let Some(value) = value {
+        return Err(HandshakeError::new(format!("{} parameter must not have a value, got {:?}", name, value)).into());
+    }
+
+    Ok(())
+}
+
+fn check_unique<'a>(seen: &mut Vec<&'a str>, name: &'a str) -> Result<()> {
+    if seen.contains(&name) {
+        return Err(HandshakeError::new(format!("{} parameter must not appear more than once", name)).into());
+    }
+
+    seen.push(name);
+    Ok(())
+}
+
+impl DeflateConfig {
+    /// Returns a `DeflateConfig` with the default parameters: 15-bit windows in both directions, with context
+    /// takeover allowed.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the base-2 logarithm of the LZ77 window size that the client uses to compress messages.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `bits` is not between 9 and 15.
+    #[must_use]
+    pub fn with_client_max_window_bits(mut self, bits: u8) -> Self {
+        check_window_bits(bits);
+        self.client_max_window_bits = bits;
+        self
+    }
+
+    /// Sets the base-2 logarithm of the LZ77 window size that the server uses to compress messages.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `bits` is not between 9 and 15.
+    #[must_use]
+    pub fn with_server_max_window_bits(mut self, bits: u8) -> Self {
+        check_window_bits(bits);
+        self.server_max_window_bits = bits;
+        self
+    }
+
+    /// Sets whether the client resets its compression context after each message.
+    #[must_use]
+    pub fn with_client_no_context_takeover(mut self, no_context_takeover: bool) -> Self {
+        self.client_no_context_takeover = no_context_takeover;
+        self
+    }
+
+    /// Sets whether the server resets its compression context after each message.
+    #[must_use]
+    pub fn with_server_no_context_takeover(mut self, no_context_takeover: bool) -> Self {
+        self.server_no_context_takeover = no_context_takeover;
+        self
+    }
+
+    /// Sets the length in bytes below which outgoing messages are sent uncompressed.
+    ///
+    /// This parameter is not negotiated with the peer.
+    #[must_use]
+    pub fn with_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Sets the zlib compression level, between 0 (no compression) and 9 (best compression).
+    ///
+    /// This parameter is not negotiated with the peer.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `level` is greater than 9.
+    #[must_use]
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        assert!(level <= 9, "compression level must be between 0 and 9, not {}", level);
+        self.compression_level = level;
+        self
+    }
+
+    /// Limits the length in bytes of each message received, after it is decompressed. The default is 64 MiB.
+    ///
+    /// The decoder returns a [`SizeLimitError`](crate::SizeLimitError) as soon as a decompressed message grows beyond
+    /// this length. This parameter is not negotiated with the peer.
+    #[must_use]
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: usize) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// Returns the base-2 logarithm of the LZ77 window size that the client uses to compress messages.
+    #[must_use]
+    pub fn client_max_window_bits(&self) -> u8 {
+        self.client_max_window_bits
+    }
+
+    /// Returns the base-2 logarithm of the LZ77 window size that the server uses to compress messages.
+    #[must_use]
+    pub fn server_max_window_bits(&self) -> u8 {
+        self.server_max_window_bits
+    }
+
+    /// Returns whether the client resets its compression context after each message.
+    #[must_use]
+    pub fn client_no_context_takeover(&self) -> bool {
+        self.client_no_context_takeover
+    }
+
+    /// Returns whether the server resets its compression context after each message.
+    #[must_use]
+    pub fn server_no_context_takeover(&self) -> bool {
+        self.server_no_context_takeover
+    }
+
+    /// Returns the value for the client's `Sec-WebSocket-Extensions` header, which offers these parameters to the
+    /// server.
+    #[must_use]
+    pub fn offer(&self) -> String {
+        let mut s = EXTENSION_NAME.to_owned();
+        if self.client_no_context_takeover {
+            s += "; client_no_context_takeover";
+        }
+
+        if self.server_no_context_takeover {
+            s += "; server_no_context_takeover";
+        }
+
+        if self.server_max_window_bits < 15 {
+            let _ = write!(s, "; server_max_window_bits={}", self.server_max_window_bits);
+        }
+
+        if self.client_max_window_bits < 15 {
+            let _ = write!(s, "; client_max_window_bits={}", self.client_max_window_bits);
+        } else {
+            s += "; client_max_window_bits";
+        }
+
+        s
+    }
+
+    /// Validates the server's `Sec-WebSocket-Extensions` response header against the parameters offered by the
+    /// client.
+    ///
+    /// Returns the parameters agreed by both endpoints, or `None` if the server declined compression.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the server accepted an extension or a parameter that the client did not offer.
+    pub fn accept_response(&self, response: Option<&str>) -> Result<Option<Self>> {
+        let response = if let Some(response) = response {
+            response
+        } else {
+            return Ok(None);
+        };
+
+        let mut extensions = header::parse_extensions(response)?.into_iter();
+        let extension = if let Some(extension) = extensions.next() {
+            extension
+        } else {
+            return Ok(None);
+        };
+
+        if extension.name != EXTENSION_NAME || extensions.next().is_some() {
+            return Err(HandshakeError::new(format!(
+                "server accepted an extension that the client did not offer: {}",
+                response
+            ))
+            .into());
+        }
+
+        self.accept_response_params(&extension.params).map(Some)
+    }
+
+    pub(crate) fn accept_response_params(&self, params: &[(&str, Option<&str>)]) -> Result<Self> {
+        let mut agreed = Self {
+            server_max_window_bits: 15,
+            server_no_context_takeover: false,
+            ..self.clone()
+        };
+
+        let mut seen = Vec::new();
+        for &(name, value) in params {
+            check_unique(&mut seen, name)?;
+            match name {
+                "server_no_context_takeover" => {
+                    check_no_value(name, value)?;
+                    agreed.server_no_context_takeover = true;
+                }
+                "client_no_context_takeover" => {
+                    check_no_value(name, value)?;
+                    agreed.client_no_context_takeover = true;
+                }
+                "server_max_window_bits" => {
+                    let bits = parse_window_bits(name, value)?;
+                    if bits > self.server_max_window_bits {
+                        return Err(HandshakeError::new(format!(
+                            "server_max_window_bits={} is larger than the {} offered by the client",
+                            bits, self.server_max_window_bits
+                        ))
+                        .into());
+                    }
+
+                    agreed.server_max_window_bits = bits;
+                }
+                "client_max_window_bits" => {
+                    let bits = parse_window_bits(name, value)?;
+                    if bits < 9 {
+                        return Err(HandshakeError::new("client_max_window_bits=8 is not supported").into());
+                    }
+
+                    agreed.client_max_window_bits = bits.min(self.client_max_window_bits);
+                }
+                _ => {
+                    return Err(HandshakeError::new(format!(
+                        "server accepted an unknown {} parameter: {}",
+                        EXTENSION_NAME, name
+                    ))
+                    .into());
+                }
+            }
+        }
+
+        Ok(agreed)
+    }
+
+    /// Chooses one of the offers in the client's `Sec-WebSocket-Extensions` request header, using the server's
+    /// parameters in `self`.
+    ///
+    /// Returns the parameters agreed by both endpoints, along with the value for the server's
+    /// `Sec-WebSocket-Extensions` response header. Returns `None` if the client did not offer compression with
+    /// parameters that the server can accept.
+    #[must_use]
+    pub fn accept_offer(&self, offers: &str) -> Option<(Self, String)> {
+        header::parse_extensions(offers)
+            .ok()?
+            .into_iter()
+            .filter(|extension| extension.name == EXTENSION_NAME)
+            .find_map(|extension| self.accept_one_offer(&extension))
+    }
+
+    fn accept_one_offer(&self, extension: &ExtensionHeader<'_>) -> Option<(Self, String)> {
+        let mut agreed = self.clone();
+        let mut client_max_window_bits_offered = false;
+        let mut seen = Vec::new();
+        for &(name, value) in &extension.params {
+            check_unique(&mut seen, name).ok()?;
+            match name {
+                "server_no_context_takeover" => {
+                    check_no_value(name, value).ok()?;
+                    agreed.server_no_context_takeover = true;
+                }
+                "client_no_context_takeover" => {
+                    check_no_value(name, value).ok()?;
+                    agreed.client_no_context_takeover = true;
+                }
+                "server_max_window_bits" => {
+                    let bits = parse_window_bits(name, value).ok()?;
+                    if bits < 9 {
+                        // zlib can't compress using a raw deflate window of 8 bits
+                        return None;
+                    }
+
+                    agreed.server_max_window_bits = agreed.server_max_window_bits.min(bits);
+                }
+                "client_max_window_bits" => {
+                    client_max_window_bits_offered = true;
+                    if value.is_some() {
+                        let bits = parse_window_bits(name, value).ok()?;
+                        agreed.client_max_window_bits = agreed.client_max_window_bits.min(bits);
+                    }
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+
+        if !client_max_window_bits_offered {
+            // The server must not ask the client to use a smaller window unless the client offered to
+            agreed.client_max_window_bits = 15;
+        }
+
+        let mut response = EXTENSION_NAME.to_owned();
+        if agreed.server_no_context_takeover {
+            response += "; server_no_context_takeover";
+        }
+
+        if agreed.client_no_context_takeover {
+            response += "; client_no_context_takeover";
+        }
+
+        if agreed.server_max_window_bits < 15 {
+            let _ = write!(response, "; server_max_window_bits={}", agreed.server_max_window_bits);
+        }
+
+        if agreed.client_max_window_bits < 15 {
+            let _ = write!(response, "; client_max_window_bits={}", agreed.client_max_window_bits);
+        }
+
+        Some((agreed, response))
+    }
+}
+
+/// Holds the compression and decompression state for one endpoint of a connection.
+pub(crate) struct DeflateContext {
+    config: DeflateConfig,
+    is_client: bool,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl DeflateContext {
+    pub(crate) fn new(config: DeflateConfig, is_client: bool) -> Self {
+        let window_bits = if is_client {
+            config.client_max_window_bits
+        } else {
+            config.server_max_window_bits
+        };
+
+        let compress = Compress::new_with_window_bits(Compression::new(config.compression_level), false, window_bits);
+
+        // A decompressor with the maximum window size can decompress data compressed with any smaller window
+        let decompress = Decompress::new(false);
+
+        Self {
+            config,
+            is_client,
+            compress,
+            decompress,
+        }
+    }
+
+    /// Returns the window bits and compression level used to compress messages, if each message is compressed
+    /// independently of the ones before it. Messages compressed with the same settings are then identical, whichever
+    /// connection they were compressed for.
+    pub(crate) fn independent_compression(&self) -> Option<(u8, u32)> {
+        let (window_bits, no_context_takeover) = if self.is_client {
+            (
+                self.config.client_max_window_bits,
+                self.config.client_no_context_takeover,
+            )
+        } else {
+            (
+                self.config.server_max_window_bits,
+                self.config.server_no_context_takeover,
+            )
+        };
+
+        if no_context_takeover {
+            Some((window_bits, self.config.compression_level))
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn should_compress(&self, data_len: usize) -> bool {
+        data_len >= self.config.compression_threshold
+    }
+
+    pub(crate) fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() / 2 + 64);
+        let mut input = data;
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut output, FlushCompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+            #[allow(clippy::cast_possible_truncation)]
+            let consumed = (self.compress.total_in() - total_in) as usize;
+            input = &input[consumed..];
+
+            // zlib has flushed all of its output once it stops filling the buffer
+            if input.is_empty() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&TRAILER) {
+            output.truncate(output.len() - TRAILER.len());
+        }
+
+        let no_context_takeover = if self.is_client {
+            self.config.client_no_context_takeover
+        } else {
+            self.config.server_no_context_takeover
+        };
+
+        if no_context_takeover {
+            self.compress.reset();
+        }
+
+        Ok(output)
+    }
+
+    /// Decompresses one frame of a compressed message. The final frame has `fin` set.
+    ///
+    /// The `decoded_len` parameter gives the number of bytes already decompressed from earlier frames in the same
+    /// message, which count towards `max_message_size` and the configured maximum decompressed size.
+    pub(crate) fn decompress_frame(
+        &mut self,
+        data: &[u8],
+        fin: bool,
+        decoded_len: usize,
+        max_message_size: Option<usize>,
+    ) -> Result<Vec<u8>> {
+        let max = max_message_size.map_or(self.config.max_decompressed_size, |max| {
+            max.min(self.config.max_decompressed_size)
+        });
+        let max_len = max.saturating_sub(decoded_len);
+        let mut output = Vec::with_capacity(data.len().saturating_mul(2).max(64).min(max_len.saturating_add(1)));
+        let mut stream_end = false;
+        let trailer: &[u8] = if fin { &TRAILER } else { &[] };
+
+        for &input in &[data, trailer] {
+            let mut input = input;
+            loop {
+                if output.len() == output.capacity() {
+                    // Grow the buffer, but never beyond one byte past the limit
+                    let additional = output.capacity().max(64).min(max_len.saturating_add(1) - output.len());
+                    output.reserve_exact(additional);
+                }
+
+                let total_in = self.decompress.total_in();
+                let total_out = self.decompress.total_out();
+                let status = self
+                    .decompress
+                    .decompress_vec(input, &mut output, FlushDecompress::Sync)
+                    .map_err(|e| ProtocolError::new(format!("compressed message is corrupt: {}", e)))?;
+
+                if output.len() > max_len {
+                    let actual = decoded_len.saturating_add(output.len());
+                    return Err(SizeLimitError::new(SizeLimit::MessageSize, max, actual).into());
+                }
+
+                #[allow(clippy::cast_possible_truncation)]
+                let consumed = (self.decompress.total_in() - total_in) as usize;
+                input = &input[consumed..];
+
+                if status == Status::StreamEnd {
+                    stream_end = true;
+                    break;
+                }
+
+                if input.is_empty() && output.len() < output.capacity() {
+                    break;
+                }
+
+                if consumed == 0 && self.decompress.total_out() == total_out && output.len() < output.capacity() {
+                    return Err(ProtocolError::new("compressed message is corrupt").into());
+                }
+            }
+
+            if stream_end {
+                break;
+            }
+        }
+
+        let no_context_takeover = if self.is_client {
+            self.config.server_no_context_takeover
+        } else {
+            self.config.client_no_context_takeover
+        };
+
+        if stream_end || (fin && no_context_takeover) {
+            self.decompress.reset(false);
+        }
+
+        Ok(output)
+    }
+}
+
+impl Clone for DeflateContext {
+    /// Returns a `DeflateContext` with the same configuration, but with fresh compression and decompression
+    /// contexts.
+    fn clone(&self) -> Self {
+        Self::new(self.config.clone(), self.is_client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deflate::{DeflateConfig, DeflateContext};
+    use crate::limit::SizeLimit;
+    use crate::Error;
+
+    #[test]
+    fn offers_parameters() {
+        assert_eq!(
+            DeflateConfig::new().offer(),
+            "permessage-deflate; client_max_window_bits"
+        );
+
+        assert_eq!(
+            DeflateConfig::new()
+                .with_server_no_context_takeover(true)
+                .with_server_max_window_bits(10)
+                .with_client_max_window_bits(12)
+                .offer(),
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=10; client_max_window_bits=12"
+        );
+    }
+
+    #[test]
+    fn server_accepts_offer() {
+        let server = DeflateConfig::new().with_server_max_window_bits(12);
+
+        let (agreed, response) = server
+            .accept_offer("x-unknown, permessage-deflate; client_max_window_bits=10; client_no_context_takeover")
+            .unwrap();
+
+        assert_eq!(
+            response,
+            "permessage-deflate; client_no_context_takeover; server_max_window_bits=12; client_max_window_bits=10"
+        );
+
+        assert_eq!(agreed.client_max_window_bits(), 10);
+        assert_eq!(agreed.server_max_window_bits(), 12);
+        assert!(agreed.client_no_context_takeover());
+        assert!(!agreed.server_no_context_takeover());
+    }
+
+    #[test]
+    fn server_skips_unacceptable_offers() {
+        let server = DeflateConfig::new();
+        let (agreed, response) = server
+            .accept_offer(
+                "permessage-deflate; server_max_window_bits=8, permessage-deflate; x-unknown, permessage-deflate",
+            )
+            .unwrap();
+
+        assert_eq!(response, "permessage-deflate");
+        assert_eq!(agreed, server);
+        assert!(server.accept_offer("x-unknown").is_none());
+    }
+
+    #[test]
+    fn client_accepts_response() {
+        let client = DeflateConfig::new();
+        assert_eq!(client.accept_response(None).unwrap(), None);
+
+        let agreed = client
+            .accept_response(Some(
+                "permessage-deflate; server_no_context_takeover; client_max_window_bits=11",
+            ))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(agreed.client_max_window_bits(), 11);
+        assert!(agreed.server_no_context_takeover());
+
+        client
+            .accept_response(Some("permessage-deflate; x-unknown"))
+            .unwrap_err();
+        client.accept_response(Some("x-unknown")).unwrap_err();
+        client
+            .accept_response(Some(
+                "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+            ))
+            .unwrap_err();
+
+        DeflateConfig::new()
+            .with_server_max_window_bits(10)
+            .accept_response(Some("permessage-deflate; server_max_window_bits=12"))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn decompresses_rfc_example() {
+        // RFC 7692 section 7.2.3.1: "Hello" compressed with no BFINAL bit
+        let mut context = DeflateContext::new(DeflateConfig::new(), true);
+        let data = context
+            .decompress_frame(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], true, 0, None)
+            .unwrap();
+
+        assert_eq!(data, b"Hello");
+
+        // RFC 7692 section 7.2.3.2: the same message again, using the sliding window
+        let data = context
+            .decompress_frame(&[0xf2, 0x00, 0x11, 0x00, 0x00], true, 0, None)
+            .unwrap();
+        assert_eq!(data, b"Hello");
+    }
+
+    #[test]
+    fn round_trips_with_context_takeover() {
+        for &no_context_takeover in &[false, true] {
+            let config = DeflateConfig::new()
+                .with_client_no_context_takeover(no_context_takeover)
+                .with_client_max_window_bits(9);
+
+            let mut client = DeflateContext::new(config.clone(), true);
+            let mut server = DeflateContext::new(config, false);
+            let data = "Hello, world! ".repeat(100);
+            for _ in 0..3 {
+                let compressed = client.compress(data.as_bytes()).unwrap();
+                assert!(compressed.len() < data.len() / 10);
+                assert_eq!(
+                    server.decompress_frame(&compressed, true, 0, None).unwrap(),
+                    data.as_bytes()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn limits_decompressed_size() {
+        let mut client = DeflateContext::new(DeflateConfig::new(), true);
+        let mut server = DeflateContext::new(DeflateConfig::new(), false);
+        let compressed = client.compress(&vec![0; 1_000_000]).unwrap();
+        assert!(compressed.len() < 2000);
+
+        let err = server.decompress_frame(&compressed, true, 0, Some(10_000)).unwrap_err();
+        let err = if let Error::Size(err) = err {
+            err
+        } else {
+            panic!("expected SizeLimitError, got {:?}", err)
+        };
+        assert_eq!(err.limit(), SizeLimit::MessageSize);
+        assert_eq!(err.max(), 10_000);
+
+        let mut server = DeflateContext::new(DeflateConfig::new().with_max_decompressed_size(20_000), false);
+        let err = server.decompress_frame(&compressed, true, 0, None).unwrap_err();
+        let err = if let Error::Size(err) = err {
+            err
+        } else {
+            panic!("expected SizeLimitError, got {:?}", err)
+        };
+        assert_eq!(err.max(), 20_000);
+    }
+}
@@ -0,0 +1,273 @@
+use alloc::borrow::Cow;
+#[cfg(feature = "std")]
+use alloc::borrow::ToOwned;
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{fmt, str};
+#[cfg(feature = "std")]
+use std::{error, io};
+
+#[cfg(feature = "std")]
+use httparse::Response;
+
+use crate::close::CloseCode;
+use crate::limit::SizeLimitError;
+
+/// Represents errors that can be exposed by this crate.
+///
+/// Errors caused by the peer breaking the WebSocket protocol say which close code to send in reply, through
+/// [`Error::close_code`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading from or writing to the underlying stream failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The opening handshake failed.
+    Handshake(HandshakeError),
+    /// A frame broke the WebSocket protocol, either one received from the peer or one that was about to be sent.
+    Protocol(ProtocolError),
+    /// A text message or a close reason was not valid UTF-8.
+    Utf8(str::Utf8Error),
+    /// The peer sent more data than one of the codec's limits allows.
+    Size(SizeLimitError),
+    /// Setting up a TLS connection failed.
+    #[cfg(feature = "std")]
+    Tls(Box<dyn error::Error + Send + Sync>),
+    /// The server's URL could not be used to connect.
+    #[cfg(feature = "std")]
+    Url(Box<dyn error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Returns the code to send to the peer when closing the connection because of this error.
+    ///
+    /// Returns `None` for errors that don't relate to data received from the peer, such as I/O errors, or that
+    /// happen before the WebSocket connection is established, such as handshake failures.
+    #[must_use]
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            Self::Protocol(e) => Some(e.close_code()),
+            Self::Utf8(_) => Some(CloseCode::Invalid),
+            Self::Size(e) => Some(e.close_code()),
+            Self::Handshake(_) => None,
+            #[cfg(feature = "std")]
+            Self::Io(_) | Self::Tls(_) | Self::Url(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Handshake(e) => write!(f, "{}", e),
+            Self::Protocol(e) => write!(f, "{}", e),
+            Self::Utf8(e) => write!(f, "{}", e),
+            Self::Size(e) => write!(f, "{}", e),
+            #[cfg(feature = "std")]
+            Self::Tls(e) | Self::Url(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Handshake(e) => Some(e),
+            Self::Protocol(e) => Some(e),
+            Self::Utf8(e) => Some(e),
+            Self::Size(e) => Some(e),
+            Self::Tls(e) | Self::Url(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<HandshakeError> for Error {
+    fn from(e: HandshakeError) -> Self {
+        Self::Handshake(e)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(e: ProtocolError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+impl From<str::Utf8Error> for Error {
+    fn from(e: str::Utf8Error) -> Self {
+        Self::Utf8(e)
+    }
+}
+
+impl From<SizeLimitError> for Error {
+    fn from(e: SizeLimitError) -> Self {
+        Self::Size(e)
+    }
+}
+
+/// Describes why the opening handshake failed.
+///
+/// When the server responded, but not with a valid WebSocket upgrade, the error holds the response's HTTP status and
+/// headers. This allows a client to tell, for instance, a `401 Unauthorized` response from a `503 Service
+/// Unavailable` response with a `Retry-After` header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HandshakeError {
+    message: Cow<'static, str>,
+    status: Option<u16>,
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+impl HandshakeError {
+    /// Creates a `HandshakeError` with a description of what went wrong.
+    ///
+    /// Extensions can return this error from [`Extension::accept_response`](crate::Extension::accept_response) to
+    /// reject the server's response.
+    #[must_use]
+    pub fn new(message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            message: message.into(),
+            status: None,
+            headers: Vec::new(),
+        }
+    }
+
+    // Attaches the status and headers of the server's response, unless the error already has them
+    #[cfg(feature = "std")]
+    pub(crate) fn with_response(mut self, response: &Response<'_, '_>) -> Self {
+        if self.status.is_none() {
+            self.status = response.code;
+            self.headers = response
+                .headers
+                .iter()
+                .map(|header| (header.name.to_owned(), header.value.to_owned()))
+                .collect();
+        }
+
+        self
+    }
+
+    /// Returns the HTTP status code of the server's response, if the server responded.
+    #[must_use]
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Returns the headers of the server's response, in the order that the server sent them.
+    ///
+    /// The slice is empty if the server did not respond.
+    #[must_use]
+    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header in the server's response with the given name, ignoring case.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for HandshakeError {}
+
+/// Describes a frame that breaks the WebSocket protocol.
+///
+/// The connection should be closed with [`CloseCode::Protocol`], as returned by [`ProtocolError::close_code`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolError {
+    message: Cow<'static, str>,
+}
+
+impl ProtocolError {
+    /// Creates a `ProtocolError` with a description of what went wrong.
+    ///
+    /// Extensions can return this error from [`Extension::decode`](crate::Extension::decode) to reject a frame.
+    #[must_use]
+    pub fn new(message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    /// Returns the code to send to the peer when closing the connection, which is always [`CloseCode::Protocol`].
+    #[must_use]
+    pub fn close_code(&self) -> CloseCode {
+        CloseCode::Protocol
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use crate::close::CloseCode;
+    use crate::error::{Error, HandshakeError, ProtocolError};
+    use crate::limit::{SizeLimit, SizeLimitError};
+
+    #[test]
+    fn protocol_level_errors_have_close_codes() {
+        let invalid = vec![0xff];
+        let utf8 = str::from_utf8(&invalid).unwrap_err();
+        assert_eq!(
+            Error::from(ProtocolError::new("bad frame")).close_code(),
+            Some(CloseCode::Protocol)
+        );
+        assert_eq!(Error::from(utf8).close_code(), Some(CloseCode::Invalid));
+        assert_eq!(
+            Error::from(SizeLimitError::new(SizeLimit::FrameSize, 10, 20)).close_code(),
+            Some(CloseCode::Size)
+        );
+        assert_eq!(Error::from(HandshakeError::new("no response")).close_code(), None);
+    }
+
+    #[test]
+    fn source_is_the_wrapped_error() {
+        use std::error::Error as _;
+        use std::io;
+
+        let err = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        let source = err.source().expect("expected the I/O error as the source");
+        let source = source
+            .downcast_ref::<io::Error>()
+            .expect("expected the source to be an io::Error");
+        assert_eq!(source.kind(), io::ErrorKind::ConnectionReset);
+
+        let err = Error::Url("invalid URL".into());
+        assert_eq!(err.source().unwrap().to_string(), "invalid URL");
+
+        let err = Error::from(ProtocolError::new("bad frame"));
+        assert!(err.source().unwrap().is::<ProtocolError>());
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
+use bytes::BytesMut;
+
+use crate::frame::FrameHeader;
+use crate::{HandshakeError, Result};
+
+/// The three RSV bits in the frame header, as returned by [`FrameHeader::rsv`].
+pub(crate) const RSV_MASK: u8 = 0x70;
+
+/// A WebSocket extension that claims one or more of the RSV bits in the frame header.
+///
+/// Extensions are negotiated during the opening handshake, through the `Sec-WebSocket-Extensions` header:
+/// - Clients pass their extensions to [`UpgradeCodec::with_extension`](crate::UpgradeCodec::with_extension), which
+///   validates the server's response through [`Extension::accept_response`].
+/// - Servers pass their extensions to [`ClientRequest::accept_extension`](crate::ClientRequest::accept_extension),
+///   which chooses one of the client's offers through [`Extension::accept_offer`].
+///
+/// Once agreed, an extension is added to the [`MessageCodec`](crate::MessageCodec) with
+/// [`MessageCodec::with_extension`](crate::MessageCodec::with_extension), which calls [`Extension::encode`] for each
+/// frame sent and [`Extension::decode`] for each frame received with the extension's RSV bits set.
+///
+/// Implement `Clone` on the extension type to satisfy the [`ExtensionClone`] bound.
+pub trait Extension: ExtensionClone + Send {
+    /// Returns the extension token that identifies this extension in the `Sec-WebSocket-Extensions` header.
+    fn name(&self) -> &str;
+
+    /// Returns the RSV bits that this extension claims, as they appear in [`FrameHeader::rsv`].
+    ///
+    /// The return value must be a non-empty combination of `0x40`, `0x20` and `0x10`.
+    fn rsv(&self) -> u8;
+
+    /// Returns the reserved opcodes that this extension gives a meaning to, from `0x3` to `0x7` and `0xB` to `0xF`.
+    ///
+    /// [`MessageCodec`](crate::MessageCodec) accepts and sends messages with these opcodes, as
+    /// [`Opcode::ReservedData`](crate::Opcode::ReservedData) and
+    /// [`Opcode::ReservedControl`](crate::Opcode::ReservedControl), while the extension is in use. The default
+    /// implementation returns no opcodes.
+    fn reserved_opcodes(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Returns the client's offer of this extension, to be sent in the `Sec-WebSocket-Extensions` request header.
+    ///
+    /// The default implementation offers the extension without parameters.
+    fn offer(&self) -> String {
+        self.name().to_owned()
+    }
+
+    /// Validates the parameters that the server returned in its `Sec-WebSocket-Extensions` response header.
+    ///
+    /// The default implementation accepts the response only if it has no parameters.
+    ///
+    /// # Errors
+    ///
+    /// Implementations return `Err` to fail the handshake, usually with a [`HandshakeError`].
+    fn accept_response(&mut self, params: &[(&str, Option<&str>)]) -> Result<()> {
+        if let Some((name, _)) = params.first() {
+            return Err(HandshakeError::new(format!(
+                "server accepted an unknown {} parameter: {}",
+                self.name(),
+                name
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Chooses whether to accept one of the client's offers of this extension, given the parameters of the offer.
+    ///
+    /// Returns the value to be sent in the server's `Sec-WebSocket-Extensions` response header, or `None` to decline
+    /// the offer. The default implementation accepts offers without parameters.
+    fn accept_offer(&mut self, params: &[(&str, Option<&str>)]) -> Option<String> {
+        if params.is_empty() {
+            Some(self.name().to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Transforms the payload of a frame about to be sent.
+    ///
+    /// Returns the RSV bits to set in the frame header, which must be a subset of [`Extension::rsv`].
+    ///
+    /// # Errors
+    ///
+    /// Errors are returned to the caller of the encoder.
+    fn encode(&mut self, header: &FrameHeader, data: &mut BytesMut) -> Result<u8>;
+
+    /// Transforms the payload of a frame received with some of this extension's RSV bits set.
+    ///
+    /// # Errors
+    ///
+    /// Errors are returned to the caller of the decoder. Return a [`ProtocolError`](crate::ProtocolError) to have the
+    /// connection closed with [`CloseCode::Protocol`](crate::CloseCode::Protocol).
+    fn decode(&mut self, header: &FrameHeader, data: &mut BytesMut) -> Result<()>;
+}
+
+/// Allows [`MessageCodec`](crate::MessageCodec) and [`UpgradeCodec`](crate::UpgradeCodec) to clone the extensions
+/// they hold.
+///
+/// This trait is implemented for every extension type that implements `Clone`.
+pub trait ExtensionClone {
+    /// Clones the extension into a new box.
+    fn clone_box(&self) -> Box<dyn Extension>;
+}
+
+impl<T: Extension + Clone + 'static> ExtensionClone for T {
+    fn clone_box(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Extension> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Panics unless `extension` claims some RSV bits, none of which appear in `claimed`.
+pub(crate) fn check_rsv(extension: &dyn Extension, claimed: u8) {
+    let rsv = extension.rsv();
+    assert!(
+        rsv != 0 && rsv & !RSV_MASK == 0,
+        "{} extension claims invalid RSV bits: 0x{:x}",
+        extension.name(),
+        rsv
+    );
+
+    assert!(
+        rsv & claimed == 0,
+        "{} extension claims RSV bits that are already in use: 0x{:x}",
+        extension.name(),
+        rsv & claimed
+    );
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use bytes::{BufMut, BytesMut};
+
+    use crate::extension::Extension;
+    use crate::frame::FrameHeader;
+    use crate::{ProtocolError, Result};
+
+    /// Appends a one-byte checksum to each data frame, and marks the frame with RSV2.
+    #[derive(Clone)]
+    pub(crate) struct Checksum;
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+    }
+
+    impl Extension for Checksum {
+        fn name(&self) -> &str {
+            "x-checksum"
+        }
+
+        fn rsv(&self) -> u8 {
+            0x20
+        }
+
+        fn encode(&mut self, header: &FrameHeader, data: &mut BytesMut) -> Result<u8> {
+            if header.opcode() >= 8 {
+                return Ok(0);
+            }
+
+            data.put_u8(checksum(data));
+            Ok(0x20)
+        }
+
+        fn decode(&mut self, _header: &FrameHeader, data: &mut BytesMut) -> Result<()> {
+            let expected = data.split_off(
+                data.len()
+                    .checked_sub(1)
+                    .ok_or_else(|| ProtocolError::new("checksum is missing"))?,
+            );
+            if checksum(data) != expected[0] {
+                return Err(ProtocolError::new("checksum does not match").into());
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn accepts_offer_without_params() {
+        assert_eq!(Checksum.accept_offer(&[]), Some("x-checksum".to_owned()));
+        assert_eq!(Checksum.accept_offer(&[("foo", None)]), None);
+    }
+
+    #[test]
+    fn rejects_response_with_params() {
+        Checksum.accept_response(&[]).unwrap();
+        Checksum.accept_response(&[("foo", Some("1"))]).unwrap_err();
+    }
+}
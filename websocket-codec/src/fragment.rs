@@ -0,0 +1,384 @@
+use alloc::format;
+
+use bytes::{Bytes, BytesMut};
+#[cfg(feature = "std")]
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::message::{DecodedFrame, Message, MessageCodec, MessageProgress};
+use crate::opcode::Opcode;
+use crate::utf8::Utf8Validator;
+#[cfg(feature = "std")]
+use crate::Error;
+use crate::{ProtocolError, Result};
+
+/// Part of a WebSocket message, as returned by [`FragmentCodec`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fragment {
+    /// The first frame of a text or binary message has arrived.
+    ///
+    /// This item is followed by zero or more [`Fragment::MessageChunk`] items, then by [`Fragment::MessageEnd`].
+    MessageStart {
+        /// Indicates whether the message contains text or binary data.
+        opcode: Opcode,
+    },
+    /// The next part of the message's payload.
+    ///
+    /// For text messages, the chunks together make up valid UTF-8, but an individual chunk may start or end partway
+    /// through a character.
+    MessageChunk(Bytes),
+    /// The final frame of the message has arrived.
+    MessageEnd,
+    /// Abandons the message being sent, after an error partway through it, so that other messages can be sent.
+    ///
+    /// This item is only ever encoded, and writes nothing. The peer has seen only part of the abandoned message, so
+    /// the connection should normally be closed afterwards.
+    MessageAbort,
+    /// A control frame: a ping, a pong or a close.
+    ///
+    /// Control frames can arrive between the chunks of a message.
+    Control(Message),
+}
+
+struct MessageState {
+    progress: MessageProgress,
+    compressed: bool,
+    decoded_len: usize,
+    is_text: bool,
+}
+
+/// Tokio codec that yields WebSocket messages in parts, as their frames arrive.
+///
+/// Unlike [`MessageCodec`], which buffers every frame of a fragmented message until the final frame arrives, this
+/// codec returns each frame's payload as soon as the frame has been received. Text payloads are validated as UTF-8
+/// incrementally, so that invalid text is rejected without waiting for the end of the message.
+///
+/// This codec encodes [`Message`] and [`Fragment`] items in the same way as [`MessageCodec`].
+pub struct FragmentCodec {
+    codec: MessageCodec,
+    message: Option<MessageState>,
+    utf8: Utf8Validator,
+    pending_chunk: Option<Bytes>,
+    pending_end: bool,
+}
+
+impl FragmentCodec {
+    /// Creates a `FragmentCodec` that decodes and encodes frames with the configuration of `codec`.
+    ///
+    /// The limits, extensions and masking set on `codec` apply to this codec too, except that
+    /// [`MessageCodec::with_max_message_size`] limits the total length of each message's chunks, which are never
+    /// held in memory all at once.
+    #[must_use]
+    pub fn new(codec: MessageCodec) -> Self {
+        Self {
+            codec,
+            message: None,
+            utf8: Utf8Validator::default(),
+            pending_chunk: None,
+            pending_end: false,
+        }
+    }
+
+    /// Creates a `FragmentCodec` for a client.
+    ///
+    /// Encoded messages are masked. Requires the `std` feature, which provides the random masks.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn client() -> Self {
+        Self::new(MessageCodec::client())
+    }
+
+    /// Creates a `FragmentCodec` for a server.
+    ///
+    /// Encoded messages are not masked.
+    #[must_use]
+    pub fn server() -> Self {
+        Self::new(MessageCodec::server())
+    }
+
+    /// Decodes the next part of a message from the front of `src`.
+    ///
+    /// Returns `None` until `src` holds the next frame. This is the same as decoding through the `Decoder` trait,
+    /// which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails in the same cases as [`MessageCodec::decode_message`].
+    pub fn decode_fragment(&mut self, src: &mut BytesMut) -> Result<Option<Fragment>> {
+        if let Some(chunk) = self.pending_chunk.take() {
+            return Ok(Some(Fragment::MessageChunk(chunk)));
+        }
+
+        if self.pending_end {
+            self.pending_end = false;
+            self.message = None;
+            return Ok(Some(Fragment::MessageEnd));
+        }
+
+        loop {
+            let progress = self.message.as_ref().map(|message| message.progress);
+            let DecodedFrame {
+                fin,
+                opcode,
+                compressed,
+                data,
+            } = if let Some(frame) = self.codec.decode_frame(src, progress)? {
+                frame
+            } else {
+                return Ok(None);
+            };
+
+            match (opcode, &mut self.message) {
+                (Some(opcode), _) if opcode.is_control() => {
+                    if !fin {
+                        return Err(ProtocolError::new("control frames must not be fragmented").into());
+                    }
+
+                    return Ok(Some(Fragment::Control(Message::new(opcode, data)?)));
+                }
+                (Some(opcode), Some(_)) => {
+                    return Err(ProtocolError::new(format!(
+                        "continuation frame must have continuation opcode, not {:?}",
+                        opcode
+                    ))
+                    .into());
+                }
+                (Some(opcode), None) => {
+                    self.message = Some(MessageState {
+                        progress: MessageProgress {
+                            len: data.len(),
+                            fragments: 1,
+                        },
+                        compressed,
+                        decoded_len: 0,
+                        is_text: opcode == Opcode::Text,
+                    });
+
+                    self.pending_chunk = self.chunk(data, fin)?;
+                    self.pending_end = fin;
+                    return Ok(Some(Fragment::MessageStart { opcode }));
+                }
+                (None, Some(message)) => {
+                    message.progress.len += data.len();
+                    message.progress.fragments += 1;
+
+                    self.pending_end = fin;
+                    if let Some(chunk) = self.chunk(data, fin)? {
+                        return Ok(Some(Fragment::MessageChunk(chunk)));
+                    }
+
+                    if fin {
+                        self.pending_end = false;
+                        self.message = None;
+                        return Ok(Some(Fragment::MessageEnd));
+                    }
+                }
+                (None, None) => {
+                    return Err(ProtocolError::new("continuation must not be first frame").into());
+                }
+            }
+        }
+    }
+
+    fn chunk(&mut self, data: BytesMut, fin: bool) -> Result<Option<Bytes>> {
+        let message = self.message.as_mut().expect("only expected chunks within a message");
+
+        let data = if message.compressed {
+            let data = self.codec.decompress_frame(&data, fin, message.decoded_len)?;
+            message.decoded_len += data.len();
+            data
+        } else {
+            data.freeze()
+        };
+
+        if message.is_text {
+            self.utf8.feed(&data)?;
+            if fin {
+                self.utf8.finish()?;
+            }
+        }
+
+        Ok(if data.is_empty() { None } else { Some(data) })
+    }
+}
+
+impl From<MessageCodec> for FragmentCodec {
+    fn from(codec: MessageCodec) -> Self {
+        Self::new(codec)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Decoder for FragmentCodec {
+    type Item = Fragment;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Fragment>> {
+        self.decode_fragment(src)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Encoder<Message> for FragmentCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<()> {
+        self.codec.encode_message(&item, dst)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Encoder<&'a Message> for FragmentCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &Message, dst: &mut BytesMut) -> Result<()> {
+        self.codec.encode_message(item, dst)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Encoder<Fragment> for FragmentCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Fragment, dst: &mut BytesMut) -> Result<()> {
+        self.codec.encode_fragment(item, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::fragment::{Fragment, FragmentCodec};
+    use crate::frame::{FrameHeader, FrameHeaderCodec};
+    use crate::message::{Message, MessageCodec};
+    use crate::opcode::Opcode;
+
+    fn frame(fin: bool, opcode: u8, data: &[u8]) -> BytesMut {
+        let header = FrameHeader {
+            fin,
+            rsv: 0,
+            opcode,
+            mask: None,
+            data_len: data.len().into(),
+        };
+
+        let mut bytes = BytesMut::new();
+        FrameHeaderCodec.encode(&header, &mut bytes).unwrap();
+        bytes.put_slice(data);
+        bytes
+    }
+
+    fn decode_all(codec: &mut FragmentCodec, src: &mut BytesMut) -> Vec<Fragment> {
+        let mut fragments = Vec::new();
+        while let Some(fragment) = codec.decode(src).unwrap() {
+            fragments.push(fragment);
+        }
+
+        fragments
+    }
+
+    fn chunk(data: &'static str) -> Fragment {
+        Fragment::MessageChunk(Bytes::from_static(data.as_bytes()))
+    }
+
+    #[test]
+    fn yields_chunks_as_frames_arrive() {
+        let text = "caf\u{e9} \u{1f600}".as_bytes();
+        let mut codec = FragmentCodec::client();
+        let mut src = frame(false, 1, &text[..4]);
+        src.unsplit(frame(true, 9, b"ping"));
+        src.unsplit(frame(false, 0, &text[4..7]));
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            vec![
+                Fragment::MessageStart { opcode: Opcode::Text },
+                Fragment::MessageChunk(Bytes::copy_from_slice(&text[..4])),
+                Fragment::Control(Message::ping("ping")),
+                Fragment::MessageChunk(Bytes::copy_from_slice(&text[4..7])),
+            ]
+        );
+
+        let mut src = frame(false, 0, b"");
+        src.unsplit(frame(true, 0, &text[7..]));
+        src.unsplit(frame(true, 2, b"bin"));
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            vec![
+                Fragment::MessageChunk(Bytes::copy_from_slice(&text[7..])),
+                Fragment::MessageEnd,
+                Fragment::MessageStart { opcode: Opcode::Binary },
+                chunk("bin"),
+                Fragment::MessageEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_text_before_the_final_frame() {
+        let mut codec = FragmentCodec::client();
+        let mut src = frame(false, 1, b"ok");
+        src.unsplit(frame(false, 0, b"\xff"));
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Fragment::MessageStart { opcode: Opcode::Text })
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(chunk("ok")));
+        codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject invalid UTF-8");
+    }
+
+    #[test]
+    fn rejects_incomplete_text_at_the_final_frame() {
+        let mut codec = FragmentCodec::client();
+        let mut src = frame(true, 1, b"\xe2\x82");
+        codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject invalid UTF-8");
+    }
+
+    #[test]
+    fn applies_message_size_limit_to_all_chunks() {
+        let mut codec = FragmentCodec::new(MessageCodec::client().with_max_message_size(4));
+        let mut src = frame(false, 2, b"abc");
+        src.unsplit(frame(true, 0, b"de"));
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Fragment::MessageStart { opcode: Opcode::Binary })
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(chunk("abc")));
+        codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject a message over the limit");
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn decompresses_each_frame() {
+        use crate::deflate::DeflateConfig;
+
+        let text = "Hello, world! ".repeat(100);
+        let mut buf = BytesMut::new();
+        MessageCodec::server()
+            .with_deflate(DeflateConfig::new())
+            .encode(Message::text(text.clone()), &mut buf)
+            .unwrap();
+
+        // Split the compressed payload across two frames
+        let payload = buf.split_off(2);
+        let mut src = frame(false, 1, &payload[..payload.len() / 2]);
+        src[0] |= 0x40;
+        src.unsplit(frame(true, 0, &payload[payload.len() / 2..]));
+
+        let mut codec = FragmentCodec::new(MessageCodec::client().with_deflate(DeflateConfig::new()));
+        let mut received = Vec::new();
+        for fragment in decode_all(&mut codec, &mut src) {
+            if let Fragment::MessageChunk(data) = fragment {
+                received.extend_from_slice(&data);
+            }
+        }
+
+        assert_eq!(received, text.as_bytes());
+    }
+}
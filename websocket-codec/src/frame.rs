@@ -1,12 +1,18 @@
-use std::convert::TryFrom;
-use std::{mem, usize};
+use alloc::format;
+use core::convert::TryFrom;
+use core::{mem, usize};
 
 use byteorder::{BigEndian, ByteOrder, NativeEndian};
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+#[cfg(feature = "std")]
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::mask::Mask;
-use crate::{Error, Result};
+use crate::limit::{SizeLimit, SizeLimitError};
+use crate::mask::{self, Mask};
+use crate::{Error, ProtocolError, Result};
+
+/// The first of the three RSV bits in the frame header, as returned by [`FrameHeader::rsv`].
+pub(crate) const RSV1: u8 = 0x40;
 
 /// Describes the length of the payload data within an individual WebSocket frame.
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -41,18 +47,26 @@ impl TryFrom<DataLength> for u64 {
             DataLength::Small(n) => Ok(u64::from(n)),
             DataLength::Medium(n) => {
                 if n <= 125 {
-                    return Err(format!("payload length {} should not be represented using 16 bits", n).into());
+                    return Err(ProtocolError::new(format!(
+                        "payload length {} should not be represented using 16 bits",
+                        n
+                    ))
+                    .into());
                 }
 
                 Ok(u64::from(n))
             }
             DataLength::Large(n) => {
                 if n <= 65535 {
-                    return Err(format!("payload length {} should not be represented using 64 bits", n).into());
+                    return Err(ProtocolError::new(format!(
+                        "payload length {} should not be represented using 64 bits",
+                        n
+                    ))
+                    .into());
                 }
 
                 if n >= 0x8000_0000_0000_0000 {
-                    return Err(format!("frame is too long: {} bytes ({:x})", n, n).into());
+                    return Err(ProtocolError::new(format!("frame is too long: {} bytes ({:x})", n, n)).into());
                 }
 
                 Ok(n as u64)
@@ -74,11 +88,11 @@ impl TryFrom<DataLength> for usize {
     fn try_from(len: DataLength) -> Result<Self> {
         let len = u64::try_from(len)?;
         if len > usize::MAX as u64 {
-            return Err(format!(
+            return Err(ProtocolError::new(format!(
                 "frame of {} bytes can't be parsed on a {}-bit platform",
                 len,
                 mem::size_of::<usize>() / 8
-            )
+            ))
             .into());
         }
 
@@ -164,7 +178,13 @@ impl FrameHeader {
         len
     }
 
-    pub(crate) fn parse_slice(buf: &[u8]) -> Option<(Self, usize)> {
+    /// Parses the frame header at the start of `buf`, returning the header and its length in bytes.
+    ///
+    /// Returns `None` if `buf` is too short to hold the whole frame header.
+    #[must_use]
+    // The length assertion below can't fail, because lengths 126 and 127 are matched first
+    #[allow(clippy::missing_panics_doc)]
+    pub fn parse_slice(buf: &[u8]) -> Option<(Self, usize)> {
         if buf.len() < 2 {
             return None;
         }
@@ -224,7 +244,12 @@ impl FrameHeader {
         Some((header, header_len))
     }
 
-    pub(crate) fn write_to_slice(&self, dst: &mut [u8]) {
+    /// Writes the frame header to the start of `dst`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is shorter than [`FrameHeader::header_len`].
+    pub fn write_to_slice(&self, dst: &mut [u8]) {
         let FrameHeader {
             fin,
             rsv,
@@ -274,9 +299,15 @@ impl FrameHeader {
             DataLength::Large(n) => n as usize,
         };
 
+        dst.reserve(self.header_len() + data_len);
+        self.write_header_to_bytes(dst);
+    }
+
+    // Writes the header without reserving space for the payload, for callers that write the payload separately
+    pub(crate) fn write_header_to_bytes(&self, dst: &mut BytesMut) {
         let initial_len = dst.len();
         let header_len = self.header_len();
-        dst.reserve(header_len + data_len);
+        dst.reserve(header_len);
 
         unsafe {
             dst.set_len(initial_len + header_len);
@@ -287,20 +318,87 @@ impl FrameHeader {
     }
 }
 
+/// Splits the next whole frame off the front of `src`, returning its header and its payload.
+///
+/// The `check` function sees the header and the payload length before any buffer space is reserved for the payload,
+/// and can reject the frame.
+pub(crate) fn split_frame(
+    src: &mut BytesMut,
+    check: impl FnOnce(&FrameHeader, usize) -> Result<()>,
+) -> Result<Option<(FrameHeader, BytesMut)>> {
+    let (header, header_len) = if let Some(tuple) = FrameHeader::parse_slice(src) {
+        tuple
+    } else {
+        // The buffer isn't big enough for the frame header. Reserve additional space for a frame header,
+        // plus reasonable extensions.
+        src.reserve(512);
+        return Ok(None);
+    };
+
+    let data_len = usize::try_from(header.data_len)?;
+    check(&header, data_len)?;
+
+    let frame_len = header_len + data_len;
+    if frame_len > src.remaining() {
+        // The buffer contains the frame header but it's not big enough for the data. Reserve additional
+        // space for the frame data, plus the next frame header.
+        // Note that we guard against bad data that indicates an unreasonable frame length.
+
+        // If we reserved buffer space for the entire frame data in a single call, would the buffer exceed
+        // usize::MAX bytes in size?
+        // On a 64-bit platform we should not reach here as the usize::try_from line above enforces the
+        // max payload length detailed in the RFC of 2^63 bytes.
+        if frame_len > usize::MAX - src.remaining() {
+            return Err(ProtocolError::new(format!("frame is too long: {0} bytes ({0:x})", frame_len)).into());
+        }
+
+        // We don't really reserve space for the entire frame data in a single call. If somebody is sending
+        // more than a gigabyte of data in a single frame then we'll still try to receive it, we'll just
+        // reserve in 1GB chunks.
+        src.reserve(frame_len.min(0x4000_0000) + 512);
+        return Ok(None);
+    }
+
+    // The buffer contains the frame header and all of the data.
+    let mut data = src.split_to(frame_len);
+    data.advance(header_len);
+    Ok(Some((header, data)))
+}
+
+/// Writes a frame header followed by its payload, masking the payload if the header has a mask.
+pub(crate) fn encode_frame(header: &FrameHeader, data: &[u8], dst: &mut BytesMut) {
+    header.write_to_bytes(dst);
+
+    if let Some(mask) = header.mask {
+        let offset = dst.len();
+        dst.reserve(data.len());
+
+        unsafe {
+            dst.set_len(offset + data.len());
+        }
+
+        mask::mask_slice_copy(&mut dst[offset..], data, mask);
+    } else {
+        dst.put_slice(data);
+    }
+}
+
 /// Tokio codec for the low-level header portion of WebSocket frames.
 /// This codec can send and receive [`FrameHeader`](struct.FrameHeader.html) structs.
 ///
 /// The frame header is a lower level detail of the WebSocket protocol. At the application level,
 /// use [`Message`](struct.Message.html) structs and the [`MessageCodec`](struct.MessageCodec.html).
+///
+/// Requires the `std` feature. Without it, use [`FrameHeader::parse_slice`] and [`FrameHeader::write_to_slice`].
+#[cfg(feature = "std")]
 pub struct FrameHeaderCodec;
 
+#[cfg(feature = "std")]
 impl Decoder for FrameHeaderCodec {
     type Item = FrameHeader;
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<FrameHeader>> {
-        use bytes::Buf;
-
         Ok(FrameHeader::parse_slice(src.chunk()).map(|(header, header_len)| {
             src.advance(header_len);
             header
@@ -308,6 +406,7 @@ impl Decoder for FrameHeaderCodec {
     }
 }
 
+#[cfg(feature = "std")]
 impl Encoder<FrameHeader> for FrameHeaderCodec {
     type Error = Error;
 
@@ -316,6 +415,7 @@ impl Encoder<FrameHeader> for FrameHeaderCodec {
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a> Encoder<&'a FrameHeader> for FrameHeaderCodec {
     type Error = Error;
 
@@ -325,13 +425,156 @@ impl<'a> Encoder<&'a FrameHeader> for FrameHeaderCodec {
     }
 }
 
+/// An individual WebSocket frame: a frame header followed by its payload.
+///
+/// Frames are a lower level detail of the WebSocket protocol. At the application level, use
+/// [`Message`](crate::Message) structs and the [`MessageCodec`](crate::MessageCodec).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// The frame header, which describes the payload.
+    pub header: FrameHeader,
+    /// The payload data, which must be as long as the header says.
+    ///
+    /// Whether the payload is masked depends on the [`FrameCodec`] that decoded or encodes the frame.
+    pub payload: Bytes,
+}
+
+/// Tokio codec for whole WebSocket frames, each one a header followed by its payload.
+///
+/// Unlike [`MessageCodec`](crate::MessageCodec), this codec does not join fragments together, check the opcode or
+/// the RSV bits, validate text or apply extensions. This makes it suitable for tools that inspect, generate or pass
+/// through frames byte for byte.
+///
+/// By default the payload of each [`Frame`] holds the data as sent over the wire, masked if the header has a mask.
+/// Use [`FrameCodec::with_unmasking`] to work with unmasked payloads instead.
+#[derive(Clone, Debug, Default)]
+pub struct FrameCodec {
+    unmask: bool,
+    max_frame_size: Option<usize>,
+}
+
+impl FrameCodec {
+    /// Creates a `FrameCodec` that leaves payloads as they are sent over the wire.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether payloads are unmasked.
+    ///
+    /// When `unmask` is true, the decoder unmasks the payload of each masked frame, and the encoder masks each payload
+    /// with the mask in its frame's header. Set a mask of your choosing through [`FrameHeader::new`].
+    #[must_use]
+    pub fn with_unmasking(mut self, unmask: bool) -> Self {
+        self.unmask = unmask;
+        self
+    }
+
+    /// Limits the payload length of each frame received.
+    ///
+    /// The decoder returns a [`SizeLimitError`] as soon as it sees a frame header that describes a longer payload,
+    /// without reserving buffer space for the payload.
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// Decodes the next frame from the front of `src`.
+    ///
+    /// Returns `None` until `src` holds the whole frame. This is the same as decoding through the `Decoder` trait,
+    /// which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the frame is longer than the limit set by [`FrameCodec::with_max_frame_size`].
+    pub fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<Frame>> {
+        let max_frame_size = self.max_frame_size;
+        let (header, mut payload) = if let Some(frame) = split_frame(src, |_header, data_len| match max_frame_size {
+            Some(max) if data_len > max => Err(SizeLimitError::new(SizeLimit::FrameSize, max, data_len).into()),
+            _ => Ok(()),
+        })? {
+            frame
+        } else {
+            return Ok(None);
+        };
+
+        if let (true, Some(mask)) = (self.unmask, header.mask) {
+            mask::mask_slice(&mut payload, mask);
+        }
+
+        Ok(Some(Frame {
+            header,
+            payload: payload.freeze(),
+        }))
+    }
+
+    /// Encodes a frame into `dst`.
+    ///
+    /// This is the same as encoding through the `Encoder` trait, which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the payload is not as long as the frame header says.
+    pub fn encode_frame(&mut self, item: &Frame, dst: &mut BytesMut) -> Result<()> {
+        let Frame { header, payload } = item;
+        let data_len = usize::try_from(header.data_len)?;
+        if data_len != payload.len() {
+            return Err(ProtocolError::new(format!(
+                "frame header describes a payload of {} bytes, but the payload is {} bytes long",
+                data_len,
+                payload.len()
+            ))
+            .into());
+        }
+
+        if self.unmask {
+            encode_frame(header, payload, dst);
+        } else {
+            header.write_to_bytes(dst);
+            dst.put_slice(payload);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>> {
+        self.decode_frame(src)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Encoder<Frame> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<()> {
+        self.encode_frame(&item, dst)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Encoder<&'a Frame> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &'a Frame, dst: &mut BytesMut) -> Result<()> {
+        self.encode_frame(item, dst)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use assert_allocations::assert_allocated_bytes;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
     use tokio_util::codec::{Decoder, Encoder};
 
-    use crate::frame::{FrameHeader, FrameHeaderCodec};
+    use crate::frame::{Frame, FrameCodec, FrameHeader, FrameHeaderCodec};
+    use crate::message::{Message, MessageCodec};
 
     #[quickcheck]
     fn round_trips(fin: bool, is_text: bool, mask: Option<u32>, data_len: u16) {
@@ -356,4 +599,55 @@ mod tests {
             assert_eq!(header, header2);
         });
     }
+
+    #[test]
+    fn passes_frames_through_byte_for_byte() {
+        let mut wire = BytesMut::new();
+        let mut client = MessageCodec::client().with_max_outgoing_frame_size(4);
+        client.encode(Message::text("Hello, world"), &mut wire).unwrap();
+        client.encode(Message::ping("ping"), &mut wire).unwrap();
+
+        let mut codec = FrameCodec::new();
+        let mut src = wire.clone();
+        let mut output = BytesMut::new();
+        let mut frames = 0;
+        while let Some(frame) = codec.decode(&mut src).unwrap() {
+            assert!(frame.header.mask().is_some());
+            codec.encode(frame, &mut output).unwrap();
+            frames += 1;
+        }
+
+        assert_eq!(frames, 4);
+        assert_eq!(output, wire);
+    }
+
+    #[test]
+    fn unmasks_and_masks_payloads() {
+        let frame = Frame {
+            header: FrameHeader::new(true, 0, 2, Some(0x1234_5678.into()), 5_usize.into()),
+            payload: Bytes::from_static(b"Hello"),
+        };
+
+        let mut codec = FrameCodec::new().with_unmasking(true);
+        let mut wire = BytesMut::new();
+        codec.encode(&frame, &mut wire).unwrap();
+        assert_eq!(
+            MessageCodec::server().decode(&mut wire.clone()).unwrap(),
+            Some(Message::binary(&b"Hello"[..]))
+        );
+
+        assert_eq!(codec.decode(&mut wire).unwrap(), Some(frame));
+    }
+
+    #[test]
+    fn rejects_payload_of_wrong_length() {
+        let frame = Frame {
+            header: FrameHeader::new(true, 0, 2, None, 4_usize.into()),
+            payload: Bytes::from_static(b"Hello"),
+        };
+
+        FrameCodec::new()
+            .encode(frame, &mut BytesMut::new())
+            .expect_err("expected encoder to reject a payload that doesn't match the header");
+    }
 }
@@ -0,0 +1,114 @@
+use crate::{HandshakeError, Result};
+
+/// One extension listed in a `Sec-WebSocket-Extensions` header, along with its parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ExtensionHeader<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) params: Vec<(&'a str, Option<&'a str>)>,
+}
+
+fn split_unquoted(s: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut in_quotes = false;
+    s.split(move |c| {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+
+        c == separator && !in_quotes
+    })
+}
+
+fn unquote(s: &str) -> &str {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+/// Splits the value of a header that holds a comma-separated list of tokens, such as `Connection`, skipping the
+/// empty elements allowed by RFC 7230 section 7.
+pub(crate) fn parse_tokens(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|token| !token.is_empty())
+}
+
+/// Parses the value of a `Sec-WebSocket-Extensions` header, as defined in RFC 6455 section 9.1.
+pub(crate) fn parse_extensions(value: &str) -> Result<Vec<ExtensionHeader<'_>>> {
+    let mut extensions = Vec::new();
+    for extension in split_unquoted(value, ',') {
+        if extension.trim().is_empty() {
+            continue;
+        }
+
+        let mut parts = split_unquoted(extension, ';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() {
+            return Err(HandshakeError::new(format!("extension name is missing in {:?}", value)).into());
+        }
+
+        let mut params = Vec::new();
+        for param in parts {
+            let (name, value) = match param.find('=') {
+                Some(index) => (param[..index].trim(), Some(unquote(param[index + 1..].trim()))),
+                None => (param, None),
+            };
+
+            if name.is_empty() {
+                return Err(
+                    HandshakeError::new(format!("extension parameter name is missing in {:?}", extension)).into(),
+                );
+            }
+
+            params.push((name, value));
+        }
+
+        extensions.push(ExtensionHeader { name, params });
+    }
+
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::{parse_extensions, parse_tokens, ExtensionHeader};
+
+    #[test]
+    fn parses_tokens() {
+        assert_eq!(
+            parse_tokens(" keep-alive,Upgrade , ,").collect::<Vec<_>>(),
+            vec!["keep-alive", "Upgrade"]
+        );
+        assert_eq!(parse_tokens("").count(), 0);
+    }
+
+    #[test]
+    fn parses_extensions_with_params() {
+        let extensions = parse_extensions(
+            "permessage-deflate; client_max_window_bits, permessage-deflate; server_max_window_bits=\"10\" , x-custom",
+        )
+        .unwrap();
+
+        assert_eq!(
+            extensions,
+            vec![
+                ExtensionHeader {
+                    name: "permessage-deflate",
+                    params: vec![("client_max_window_bits", None)],
+                },
+                ExtensionHeader {
+                    name: "permessage-deflate",
+                    params: vec![("server_max_window_bits", Some("10"))],
+                },
+                ExtensionHeader {
+                    name: "x-custom",
+                    params: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_missing_name() {
+        parse_extensions("permessage-deflate, ; foo").unwrap_err();
+    }
+}
@@ -2,10 +2,21 @@
 #![warn(missing_docs)]
 #![allow(clippy::module_name_repetitions)]
 #![cfg_attr(feature = "nightly", feature(test))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 //! A Tokio codec implementation of the WebSocket protocol.
 //!
 //! This crate does not do any I/O directly. For a full WebSocket client, see the [websocket-lite](https://docs.rs/websocket-lite) crate.
+//!
+//! The `deflate` feature flag provides the permessage-deflate compression extension. The `simd-utf8` feature flag
+//! validates text messages and close reasons using SSE4.2 or AVX2 instructions, chosen at runtime according to the
+//! CPU.
+//!
+//! The `std` feature flag, which is enabled by default, provides the Tokio `Decoder` and `Encoder` implementations,
+//! the opening handshake and random masks. Without it, the crate builds on `core` and `alloc`, and offers frame
+//! parsing and serialization, masking, close codes and message assembly through methods such as
+//! [`MessageCodec::decode_message`] and [`MessageCodec::encode_message`]. A codec built this way can't mask the
+//! messages it sends, so it suits servers, or clients that mask frames themselves through [`protocol::FrameCodec`].
 
 #[cfg(test)]
 #[macro_use]
@@ -14,24 +25,48 @@ extern crate quickcheck_macros;
 #[cfg(all(feature = "nightly", test))]
 extern crate test;
 
+extern crate alloc;
+
 mod close;
+mod connection;
+#[cfg(feature = "deflate")]
+mod deflate;
+mod error;
+mod extension;
+mod fragment;
 mod frame;
+#[cfg(feature = "std")]
+mod header;
+mod limit;
 mod mask;
 mod message;
 mod opcode;
+mod prepared;
+#[cfg(feature = "std")]
 mod upgrade;
+mod utf8;
 
 pub mod protocol;
 
 pub use crate::close::{CloseCode, CloseFrame};
+pub use crate::connection::{Connection, ConnectionState};
+#[cfg(feature = "deflate")]
+pub use crate::deflate::DeflateConfig;
+pub use crate::error::{Error, HandshakeError, ProtocolError};
+pub use crate::extension::{Extension, ExtensionClone};
+pub use crate::fragment::{Fragment, FragmentCodec};
+pub use crate::limit::{SizeLimit, SizeLimitError};
 pub use crate::message::{Message, MessageCodec};
 pub use crate::opcode::Opcode;
-pub use crate::upgrade::{ClientRequest, UpgradeCodec};
-
-use std::{error, result};
+pub use crate::prepared::PreparedMessage;
+#[cfg(feature = "std")]
+pub use crate::upgrade::{
+    ClientRequest, HandshakeRequest, HandshakeResponse, ServerUpgradeCodec, UpgradeCodec, UpgradeRequest,
+    UpgradeResponse,
+};
+pub use crate::utf8::Utf8Bytes;
 
-/// Represents errors that can be exposed by this crate.
-pub type Error = Box<dyn error::Error + Send + Sync + 'static>;
+use core::result;
 
 /// Represents results returned by the non-async functions in this crate.
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,70 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use crate::close::CloseCode;
+
+/// Identifies which of the [`MessageCodec`](crate::MessageCodec) size limits was exceeded.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SizeLimit {
+    /// The payload of a single frame was longer than the maximum frame size.
+    FrameSize,
+    /// The payload of a message, reassembled from all of its fragments, was longer than the maximum message size.
+    MessageSize,
+    /// The message was split into more fragments than the maximum fragment count.
+    Fragments,
+}
+
+/// Returned by [`MessageCodec`](crate::MessageCodec) when the peer sends more data than one of its limits allows.
+///
+/// The connection should be closed with [`CloseCode::Size`], as returned by [`SizeLimitError::close_code`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SizeLimitError {
+    limit: SizeLimit,
+    max: usize,
+    actual: usize,
+}
+
+impl SizeLimitError {
+    pub(crate) fn new(limit: SizeLimit, max: usize, actual: usize) -> Self {
+        Self { limit, max, actual }
+    }
+
+    /// Returns the limit that was exceeded.
+    #[must_use]
+    pub fn limit(&self) -> SizeLimit {
+        self.limit
+    }
+
+    /// Returns the value of the limit that was exceeded.
+    #[must_use]
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Returns the size or count that the peer attempted to send.
+    #[must_use]
+    pub fn actual(&self) -> usize {
+        self.actual
+    }
+
+    /// Returns the code to send to the peer when closing the connection, which is always [`CloseCode::Size`].
+    #[must_use]
+    pub fn close_code(&self) -> CloseCode {
+        CloseCode::Size
+    }
+}
+
+impl fmt::Display for SizeLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let SizeLimitError { limit, max, actual } = *self;
+        match limit {
+            SizeLimit::FrameSize => write!(f, "frame is too long: {} bytes (limit is {} bytes)", actual, max),
+            SizeLimit::MessageSize => write!(f, "message is too long: {} bytes (limit is {} bytes)", actual, max),
+            SizeLimit::Fragments => write!(f, "message has too many fragments: {} (limit is {})", actual, max),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SizeLimitError {}
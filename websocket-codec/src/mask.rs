@@ -1,9 +1,17 @@
 #![allow(clippy::new_without_default)]
 
+/// The key that a client uses to mask the payload of each frame it sends.
+///
+/// Convert a `u32` into a `Mask` to choose a particular mask.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Mask(u32);
 
 impl Mask {
+    /// Returns a random mask, as the protocol requires for each frame sent by a client.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    #[must_use]
     pub fn new() -> Self {
         rand::random::<u32>().into()
     }
@@ -43,12 +51,18 @@ pub fn mask_slice_copy(buf: &mut [u8], data: &[u8], Mask(mask): Mask) {
 fn mask_aligned_copy(buf: &mut [u32], data: &[u32], mask: u32) {
     assert_eq!(buf.len(), data.len());
 
-    for (dest, src) in buf.iter_mut().zip(data) {
+    let done = unsafe { mask_simd(buf.as_mut_ptr().cast(), data.as_ptr().cast(), buf.len() * 4, mask) } / 4;
+    for (dest, src) in buf[done..].iter_mut().zip(&data[done..]) {
         *dest = src ^ mask;
     }
 }
 
 fn mask_unaligned_copy(buf: &mut [u32], data: &[u8], mask: u32) {
+    assert_eq!(data.len(), buf.len() * 4);
+
+    let done = unsafe { mask_simd(buf.as_mut_ptr().cast(), data.as_ptr(), data.len(), mask) } / 4;
+    let (buf, data) = (&mut buf[done..], &data[done * 4..]);
+
     let data = data.chunks_exact(4);
     assert_eq!(data.len(), buf.len());
     assert_eq!(data.remainder().len(), 0);
@@ -94,11 +108,100 @@ fn mask_u8_in_place(data: &mut [u8], mut mask: u32) -> u32 {
 }
 
 fn mask_aligned_in_place(data: &mut [u32], mask: u32) {
-    for n in data {
+    let ptr = data.as_mut_ptr().cast::<u8>();
+    let done = unsafe { mask_simd(ptr, ptr, data.len() * 4, mask) } / 4;
+    for n in &mut data[done..] {
         *n ^= mask;
     }
 }
 
+/// XORs as much of `len` bytes at `src` as possible into `dst` using SIMD instructions, and returns the number of
+/// bytes processed, which is a multiple of 4.
+///
+/// The instructions are chosen at runtime according to the CPU, or at compile time without the `std` feature. Both
+/// pointers must be valid for `len` bytes, and they may point to the same place for masking in place.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe fn mask_simd(dst: *mut u8, src: *const u8, len: usize, mask: u32) -> usize {
+    if len < 16 {
+        0
+    } else if x86::has_avx2() {
+        x86::mask_avx2(dst, src, len, mask)
+    } else if x86::has_sse2() {
+        x86::mask_sse2(dst, src, len, mask)
+    } else {
+        0
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+unsafe fn mask_simd(_dst: *mut u8, _src: *const u8, _len: usize, _mask: u32) -> usize {
+    0
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::{
+        __m128i, __m256i, _mm256_loadu_si256, _mm256_set1_epi32, _mm256_storeu_si256, _mm256_xor_si256,
+        _mm_loadu_si128, _mm_set1_epi32, _mm_storeu_si128, _mm_xor_si128,
+    };
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::{
+        __m128i, __m256i, _mm256_loadu_si256, _mm256_set1_epi32, _mm256_storeu_si256, _mm256_xor_si256,
+        _mm_loadu_si128, _mm_set1_epi32, _mm_storeu_si128, _mm_xor_si128,
+    };
+
+    #[cfg(feature = "std")]
+    pub(super) fn has_avx2() -> bool {
+        is_x86_feature_detected!("avx2")
+    }
+
+    #[cfg(feature = "std")]
+    pub(super) fn has_sse2() -> bool {
+        is_x86_feature_detected!("sse2")
+    }
+
+    // Runtime detection needs std, so use the instructions that the target is compiled for
+    #[cfg(not(feature = "std"))]
+    pub(super) fn has_avx2() -> bool {
+        cfg!(target_feature = "avx2")
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(super) fn has_sse2() -> bool {
+        cfg!(target_feature = "sse2")
+    }
+
+    // The mask repeats every 4 bytes, so each 32-byte block is masked with the same 8 copies of it
+    #[target_feature(enable = "avx2")]
+    #[allow(clippy::cast_possible_wrap, clippy::cast_ptr_alignment)]
+    pub(super) unsafe fn mask_avx2(dst: *mut u8, src: *const u8, len: usize, mask: u32) -> usize {
+        let mask = _mm256_set1_epi32(mask as i32);
+        let mut offset = 0;
+        while len - offset >= 32 {
+            let data = _mm256_loadu_si256(src.add(offset).cast::<__m256i>());
+            _mm256_storeu_si256(dst.add(offset).cast::<__m256i>(), _mm256_xor_si256(data, mask));
+            offset += 32;
+        }
+
+        offset
+    }
+
+    #[target_feature(enable = "sse2")]
+    #[allow(clippy::cast_possible_wrap, clippy::cast_ptr_alignment)]
+    pub(super) unsafe fn mask_sse2(dst: *mut u8, src: *const u8, len: usize, mask: u32) -> usize {
+        let mask = _mm_set1_epi32(mask as i32);
+        let mut offset = 0;
+        while len - offset >= 16 {
+            let data = _mm_loadu_si128(src.add(offset).cast::<__m128i>());
+            _mm_storeu_si128(dst.add(offset).cast::<__m128i>(), _mm_xor_si128(data, mask));
+            offset += 16;
+        }
+
+        offset
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use assert_allocations::assert_allocated_bytes;
@@ -145,4 +248,41 @@ mod tests {
         assert_allocated_bytes(0, || mask::mask_slice(&mut data, mask));
         assert_eq!(orig_data, data);
     }
+
+    // Masks one byte at a time, for comparison with the SIMD and u32 implementations
+    fn mask_bytes(data: &[u8], mask: u32) -> Vec<u8> {
+        let mask = mask.to_ne_bytes();
+        data.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m).collect()
+    }
+
+    #[quickcheck]
+    #[allow(clippy::needless_pass_by_value)] // clippy wants &[u8], but quickcheck can only give us Vec<u8>
+    fn masks_at_any_offset(data: Vec<u8>, offset: usize, mask: u32) {
+        // Make the data long enough for the SIMD loops, and start it at an arbitrary alignment
+        let data = data.repeat(200 / (data.len() + 1) + 1);
+        let offset = offset % 8;
+        let mut buf = vec![0; data.len() + offset];
+        let expected = mask_bytes(&data, mask);
+
+        mask::mask_slice_copy(&mut buf[offset..], &data, Mask::from(mask));
+        assert_eq!(&buf[offset..], &expected[..]);
+
+        let mut data = [&[0; 8][..offset], &data].concat();
+        mask::mask_slice(&mut data[offset..], Mask::from(mask));
+        assert_eq!(&data[offset..], &expected[..]);
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn masks_with_sse2() {
+        if !is_x86_feature_detected!("sse2") {
+            return;
+        }
+
+        let data = DATA.repeat(3);
+        let mut buf = vec![0; data.len()];
+        let done = unsafe { mask::x86::mask_sse2(buf.as_mut_ptr(), data.as_ptr(), data.len(), 0x1234_5678) };
+        assert_eq!(done, data.len() / 16 * 16);
+        assert_eq!(&buf[..done], &mask_bytes(&data, 0x1234_5678)[..done]);
+    }
 }
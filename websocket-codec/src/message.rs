@@ -1,14 +1,32 @@
-use std::convert::TryFrom;
-use std::{str, usize};
-
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{str, usize};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+use bytes::{Buf, Bytes, BytesMut};
+#[cfg(feature = "std")]
+use rand::RngCore;
+#[cfg(feature = "std")]
 use tokio_util::codec::{Decoder, Encoder};
 
 use crate::close::{CloseCode, CloseFrame};
-use crate::frame::FrameHeader;
+#[cfg(feature = "deflate")]
+use crate::deflate::{DeflateConfig, DeflateContext};
+use crate::extension::{self, Extension};
+use crate::fragment::Fragment;
+use crate::frame::{self, FrameHeader, RSV1};
+use crate::limit::{SizeLimit, SizeLimitError};
 use crate::mask::Mask;
 use crate::opcode::Opcode;
-use crate::{mask, Error, Result};
+use crate::prepared::PreparedMessage;
+use crate::utf8::{self, Utf8Bytes, Utf8Validator};
+#[cfg(feature = "std")]
+use crate::Error;
+use crate::{mask, ProtocolError, Result};
 
 /// A text string, a block of binary data or a WebSocket control frame.
 #[derive(Clone, Debug, PartialEq)]
@@ -28,21 +46,24 @@ impl Message {
     /// - For [`Opcode::Text`] it returns `Err` if the bytes in `data` do not contain valid UTF-8 text.
     /// - For [`Opcode::Close`] it returns `Err` if `data` does not contain a two-byte close code
     ///   followed by valid UTF-8 text, unless `data` is empty.
+    /// - For [`Opcode::ReservedData`] and [`Opcode::ReservedControl`] it returns `Err` if the opcode is outside the
+    ///   reserved range.
     pub fn new<B: Into<Bytes>>(opcode: Opcode, data: B) -> Result<Self> {
         let data = data.into();
+        check_opcode_range(opcode)?;
 
         match opcode {
             Opcode::Close => match data.len() {
                 0 => {}
                 1 => {
-                    return Err("close frames must be at least 2 bytes long".into());
+                    return Err(ProtocolError::new("close frames must be at least 2 bytes long").into());
                 }
                 _ => {
-                    str::from_utf8(&data[2..])?;
+                    utf8::validate(&data[2..])?;
                 }
             },
             Opcode::Text => {
-                str::from_utf8(&data)?;
+                utf8::validate(&data)?;
             }
             _ => {}
         }
@@ -50,8 +71,16 @@ impl Message {
         Ok(Message { opcode, data })
     }
 
-    /// Creates a text message from a `String`.
+    /// Creates a text message from any type that can be converted to `String`, such as `&str` or `String`.
+    ///
+    /// A `String` becomes the payload without being copied. Use [`Message::text_utf8`] to avoid copying a
+    /// `&'static str`.
     pub fn text<S: Into<String>>(data: S) -> Self {
+        Self::text_utf8(data.into())
+    }
+
+    /// Creates a text message from a [`Utf8Bytes`], a `&'static str` or a `String`, without copying the text.
+    pub fn text_utf8<S: Into<Utf8Bytes>>(data: S) -> Self {
         Message {
             opcode: Opcode::Text,
             data: data.into().into(),
@@ -66,6 +95,18 @@ impl Message {
         }
     }
 
+    /// Creates a binary message that holds the only reference to its buffer.
+    ///
+    /// A client can send this message with [`MessageCodec::encode_owned`], which masks the payload in place instead
+    /// of copying it.
+    #[must_use]
+    pub fn binary_mut(data: BytesMut) -> Self {
+        Message {
+            opcode: Opcode::Binary,
+            data: data.freeze(),
+        }
+    }
+
     pub(crate) fn header(&self, mask: Option<Mask>) -> FrameHeader {
         FrameHeader {
             fin: true,
@@ -149,6 +190,28 @@ impl Message {
         }
     }
 
+    /// For messages with opcode [`Opcode::Text`], returns the text as a [`Utf8Bytes`] that shares this message's
+    /// buffer. Returns `None` otherwise.
+    #[must_use]
+    pub fn to_text(&self) -> Option<Utf8Bytes> {
+        if self.opcode.is_text() {
+            Some(Utf8Bytes::from_bytes_unchecked(self.data.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// For messages with opcode [`Opcode::Text`], consumes the message and returns its text. Returns `None`
+    /// otherwise.
+    #[must_use]
+    pub fn into_text(self) -> Option<Utf8Bytes> {
+        if self.opcode.is_text() {
+            Some(Utf8Bytes::from_bytes_unchecked(self.data))
+        } else {
+            None
+        }
+    }
+
     /// For messages with opcode [`Opcode::Close`], returns the [`CloseFrame`].
     /// Returns `None` otherwise.
     pub fn as_close(&self) -> Option<CloseFrame> {
@@ -157,7 +220,7 @@ impl Message {
             let code = data.get_u16();
             Some(CloseFrame {
                 code: code.into(),
-                reason: data,
+                reason: Utf8Bytes::from_bytes_unchecked(data),
             })
         } else {
             None
@@ -166,37 +229,518 @@ impl Message {
 }
 
 /// Tokio codec for WebSocket messages. This codec can send and receive [`Message`] structs.
+///
+/// By default the codec accepts frames and messages of any length. Use [`MessageCodec::with_max_frame_size`],
+/// [`MessageCodec::with_max_message_size`] and [`MessageCodec::with_max_fragments`] to limit the memory that a peer
+/// can make the decoder reserve.
+///
+/// The encoder sends each message as a single frame unless [`MessageCodec::with_max_outgoing_frame_size`] is set.
 #[derive(Clone)]
 pub struct MessageCodec {
-    interrupted_message: Option<(Opcode, BytesMut)>,
+    interrupted_message: Option<PartialMessage>,
     use_mask: bool,
+    strict_masking: bool,
+    max_frame_size: Option<usize>,
+    max_message_size: Option<usize>,
+    max_fragments: Option<usize>,
+    max_outgoing_frame_size: Option<usize>,
+    reserved_opcodes: bool,
+    #[cfg(feature = "deflate")]
+    deflate: Option<DeflateContext>,
+    extensions: Vec<Box<dyn Extension>>,
+    outgoing_message: Option<OutgoingMessage>,
+    // Generates masks in place of `rand::random`; shared between clones of the codec
+    #[cfg(feature = "std")]
+    rng: Option<Arc<Mutex<dyn RngCore + Send>>>,
+}
+
+#[derive(Clone)]
+struct PartialMessage {
+    opcode: Opcode,
+    // The payload of each fragment received so far, joined once the final fragment arrives, so that each byte is
+    // copied at most once
+    segments: Vec<Bytes>,
+    len: usize,
+    fragments: usize,
+    compressed: bool,
+    // Validates uncompressed text as each fragment arrives, so that invalid text is rejected before the final frame
+    utf8: Option<Utf8Validator>,
+}
+
+impl PartialMessage {
+    fn progress(&self) -> MessageProgress {
+        MessageProgress {
+            len: self.len,
+            fragments: self.fragments,
+        }
+    }
+
+    fn push(&mut self, data: BytesMut) {
+        if !data.is_empty() {
+            self.len += data.len();
+            self.segments.push(data.freeze());
+        }
+    }
+
+    fn into_data(mut self) -> Bytes {
+        if self.segments.len() <= 1 {
+            return self.segments.pop().unwrap_or_default();
+        }
+
+        let mut data = BytesMut::with_capacity(self.len);
+        for segment in self.segments {
+            data.extend_from_slice(&segment);
+        }
+
+        data.freeze()
+    }
+}
+
+/// A message being sent in fragments, between its [`Fragment::MessageStart`] and its [`Fragment::MessageEnd`].
+#[derive(Clone)]
+struct OutgoingMessage {
+    // The opcode for the first frame, or None once the first frame has been sent
+    opcode: Option<Opcode>,
+    utf8: Option<Utf8Validator>,
+}
+
+/// Describes the frames received so far for a message that has not yet seen its final frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct MessageProgress {
+    /// The total payload length of the frames received so far, as sent over the wire.
+    pub(crate) len: usize,
+    /// The number of frames received so far, not counting interleaved control frames.
+    pub(crate) fragments: usize,
+}
+
+/// A single frame, parsed by [`MessageCodec::decode_frame`] and passed through any extensions.
+pub(crate) struct DecodedFrame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: Option<Opcode>,
+    pub(crate) compressed: bool,
+    pub(crate) data: BytesMut,
 }
 
 impl MessageCodec {
     /// Creates a `MessageCodec` for a client.
     ///
-    /// Encoded messages are masked.
+    /// Encoded messages are masked. The decoder returns a [`ProtocolError`] for any masked frame, as the protocol
+    /// forbids servers from masking frames.
+    ///
+    /// Requires the `std` feature, which provides the random masks.
+    #[cfg(feature = "std")]
     #[must_use]
     pub fn client() -> Self {
-        Self::with_masked_encode(true)
+        Self {
+            strict_masking: true,
+            ..Self::with_masked_encode(true)
+        }
     }
 
     /// Creates a `MessageCodec` for a server.
     ///
-    /// Encoded messages are not masked.
+    /// Encoded messages are not masked. The decoder returns a [`ProtocolError`] for any unmasked frame, as the
+    /// protocol requires clients to mask every frame.
     #[must_use]
     pub fn server() -> Self {
-        Self::with_masked_encode(false)
+        Self {
+            strict_masking: true,
+            ..Self::new(false)
+        }
     }
 
     /// Creates a `MessageCodec` while specifying whether to use message masking while encoding.
+    ///
+    /// Unlike [`MessageCodec::client`] and [`MessageCodec::server`], the decoder accepts frames whether or not they
+    /// are masked. This suits tools that inspect or replay traffic in either direction.
+    ///
+    /// Requires the `std` feature, which provides the random masks.
+    #[cfg(feature = "std")]
     #[must_use]
     pub fn with_masked_encode(use_mask: bool) -> Self {
+        Self::new(use_mask)
+    }
+
+    fn new(use_mask: bool) -> Self {
         Self {
             use_mask,
+            strict_masking: false,
             interrupted_message: None,
+            max_frame_size: None,
+            max_message_size: None,
+            max_fragments: None,
+            max_outgoing_frame_size: None,
+            reserved_opcodes: false,
+            #[cfg(feature = "deflate")]
+            deflate: None,
+            extensions: Vec::new(),
+            outgoing_message: None,
+            #[cfg(feature = "std")]
+            rng: None,
+        }
+    }
+
+    /// Compresses and decompresses messages using the permessage-deflate extension.
+    ///
+    /// The `config` parameter holds the parameters agreed during the handshake, as returned by
+    /// [`DeflateConfig::accept_response`] or [`DeflateConfig::accept_offer`]. Masking codecs take the client's
+    /// role in the agreed parameters, and non-masking codecs take the server's role.
+    ///
+    /// Decompressed messages are subject to the limit set by [`DeflateConfig::with_max_decompressed_size`], which is
+    /// 64 MiB by default, and to any lower limit set by [`MessageCodec::with_max_message_size`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if an extension added with [`MessageCodec::with_extension`] already claims the RSV1 bit.
+    #[cfg(feature = "deflate")]
+    #[must_use]
+    pub fn with_deflate(mut self, config: DeflateConfig) -> Self {
+        assert!(
+            self.claimed_rsv() & RSV1 == 0,
+            "permessage-deflate extension claims RSV bits that are already in use: 0x{:x}",
+            RSV1
+        );
+
+        self.deflate = Some(DeflateContext::new(config, self.use_mask));
+        self
+    }
+
+    /// Adds an extension that was agreed during the handshake, allowing frames with the extension's RSV bits set.
+    ///
+    /// The extension transforms each frame sent and each frame received, after any extensions added earlier.
+    /// Received frames are passed to extensions in the opposite order, so that each extension sees the payload that its
+    /// counterpart on the other endpoint produced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the extension claims no RSV bits, or claims RSV bits that are already in use by another extension.
+    #[must_use]
+    pub fn with_extension(mut self, extension: Box<dyn Extension>) -> Self {
+        extension::check_rsv(&*extension, self.claimed_rsv());
+        self.extensions.push(extension);
+        self
+    }
+
+    /// Sets whether to accept and send messages with reserved opcodes, which the protocol sets aside for future use.
+    ///
+    /// Without this option, the codec only allows the reserved opcodes defined by its extensions, through
+    /// [`Extension::reserved_opcodes`]. Received messages with reserved opcodes are returned as
+    /// [`Opcode::ReservedData`] and [`Opcode::ReservedControl`].
+    #[must_use]
+    pub fn with_reserved_opcodes(mut self, allow: bool) -> Self {
+        self.reserved_opcodes = allow;
+        self
+    }
+
+    /// Generates the masks for encoded frames from `rng`, instead of from the thread-local random number generator.
+    ///
+    /// This is intended for testing only. Seeding `rng` makes the encoded frames reproducible, so that they can be
+    /// compared against known bytes. Masks that the peer can predict defeat the purpose of masking, so don't use a
+    /// predictable `rng` on a real connection. Clones of the codec share the same `rng`.
+    ///
+    /// Codecs that don't mask the frames they send never use `rng`.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn with_rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = Some(Arc::new(Mutex::new(rng)));
+        self
+    }
+
+    // Returns an error unless the opcode is a standard one, or the codec or one of its extensions allows it
+    fn check_opcode(&self, opcode: Opcode) -> Result<()> {
+        check_opcode_range(opcode)?;
+
+        let data = u8::from(opcode);
+        if opcode.is_reserved()
+            && !self.reserved_opcodes
+            && !self
+                .extensions
+                .iter()
+                .any(|extension| extension.reserved_opcodes().contains(&data))
+        {
+            return Err(ProtocolError::new(format!("opcode {} is not supported", data)).into());
+        }
+
+        Ok(())
+    }
+
+    fn claimed_rsv(&self) -> u8 {
+        let rsv = if self.accepts_compressed() { RSV1 } else { 0 };
+        self.extensions.iter().fold(rsv, |rsv, extension| rsv | extension.rsv())
+    }
+
+    #[cfg(feature = "deflate")]
+    fn accepts_compressed(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    #[allow(clippy::unused_self)]
+    fn accepts_compressed(&self) -> bool {
+        false
+    }
+
+    /// Decompresses one frame of a compressed message, of which `decoded_len` bytes have already been decompressed.
+    #[cfg(feature = "deflate")]
+    pub(crate) fn decompress_frame(&mut self, data: &[u8], fin: bool, decoded_len: usize) -> Result<Bytes> {
+        let max_message_size = self.max_message_size;
+        let deflate = self
+            .deflate
+            .as_mut()
+            .expect("only expected compressed messages when deflate is enabled");
+
+        Ok(deflate
+            .decompress_frame(data, fin, decoded_len, max_message_size)?
+            .into())
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    #[allow(clippy::unused_self)]
+    pub(crate) fn decompress_frame(&mut self, _data: &[u8], _fin: bool, _decoded_len: usize) -> Result<Bytes> {
+        unreachable!("only expected compressed messages when deflate is enabled")
+    }
+
+    /// Limits the payload length of each frame received.
+    ///
+    /// The decoder returns a [`SizeLimitError`] as soon as it sees a frame header that describes a longer payload,
+    /// without reserving buffer space for the payload.
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// Limits the payload length of each message received, after the message's fragments are reassembled.
+    ///
+    /// The decoder returns a [`SizeLimitError`] as soon as it sees a frame header that would take the message over
+    /// this length.
+    #[must_use]
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Limits the number of frames that a single message can be split into.
+    ///
+    /// Control frames interleaved with the fragments of a message do not count towards this limit.
+    #[must_use]
+    pub fn with_max_fragments(mut self, max_fragments: usize) -> Self {
+        self.max_fragments = Some(max_fragments);
+        self
+    }
+
+    /// Limits the payload length of each frame sent.
+    ///
+    /// The encoder splits longer text and binary messages into a first frame followed by continuation frames. Control
+    /// messages are never split, as the protocol limits them to 125 bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_outgoing_frame_size` is zero.
+    #[must_use]
+    pub fn with_max_outgoing_frame_size(mut self, max_outgoing_frame_size: usize) -> Self {
+        assert!(
+            max_outgoing_frame_size > 0,
+            "maximum outgoing frame size must not be zero"
+        );
+        self.max_outgoing_frame_size = Some(max_outgoing_frame_size);
+        self
+    }
+
+    /// Encodes a message without copying its payload into `dst`.
+    ///
+    /// This function writes the frame header to `dst` and returns the payload, to be written straight after the
+    /// header. When the codec masks outgoing frames, it masks the payload in place, which is possible only when the
+    /// message holds the only reference to its buffer, as it does when created with [`Message::binary_mut`].
+    ///
+    /// Messages that are compressed, passed through extensions or split into several frames are encoded into `dst`
+    /// in full, as are masked messages whose buffer is shared. For these messages the returned payload is empty.
+    ///
+    /// # Errors
+    ///
+    /// This function fails in the same cases as [`MessageCodec::encode_message`].
+    pub fn encode_owned(&mut self, item: Message, dst: &mut BytesMut) -> Result<Bytes> {
+        let split = match self.max_outgoing_frame_size {
+            Some(max) => item.data.len() > max,
+            None => false,
+        };
+
+        #[cfg(feature = "deflate")]
+        let compressed = match &self.deflate {
+            Some(deflate) => deflate.should_compress(item.data.len()),
+            None => false,
+        };
+
+        #[cfg(not(feature = "deflate"))]
+        let compressed = false;
+
+        let in_place = self.extensions.is_empty() && (item.opcode.is_control() || !(split || compressed));
+
+        if !in_place {
+            self.encode_message(&item, dst)?;
+            return Ok(Bytes::new());
+        }
+
+        if self.outgoing_message.is_some() && !item.opcode.is_control() {
+            return Err(
+                ProtocolError::new("can't send a message while another message is being sent in fragments").into(),
+            );
+        }
+
+        self.check_opcode(item.opcode)?;
+
+        let mask = if let Some(mask) = self.next_mask() {
+            mask
+        } else {
+            item.header(None).write_header_to_bytes(dst);
+            return Ok(item.data);
+        };
+
+        let Message { opcode, data } = item;
+        match data.try_into_mut() {
+            Ok(mut data) => {
+                let header = FrameHeader {
+                    fin: true,
+                    rsv: 0,
+                    opcode: opcode.into(),
+                    mask: Some(mask),
+                    data_len: data.len().into(),
+                };
+
+                header.write_header_to_bytes(dst);
+                mask::mask_slice(&mut data, mask);
+                Ok(data.freeze())
+            }
+            Err(data) => {
+                self.encode_message(&Message { opcode, data }, dst)?;
+                Ok(Bytes::new())
+            }
+        }
+    }
+
+    /// Encodes a message that was prepared to be sent to many peers, without encoding it again.
+    ///
+    /// Like [`MessageCodec::encode_owned`], this function writes any frame header to `dst` and returns the bytes to be
+    /// written straight after it. A codec that doesn't mask or compress the frames it sends writes nothing to `dst`,
+    /// and returns the frame held by the [`PreparedMessage`].
+    ///
+    /// When compression was agreed with `server_no_context_takeover`, or `client_no_context_takeover` for a client,
+    /// the compressed payload is shared by every codec that compresses with the same settings. With context takeover,
+    /// each connection compresses the message in its own context. Messages that are masked, passed through extensions
+    /// or split into several frames are encoded into `dst` in full, and the returned bytes are empty.
+    ///
+    /// # Errors
+    ///
+    /// This function fails in the same cases as [`MessageCodec::encode_message`].
+    pub fn encode_prepared(&mut self, item: &PreparedMessage, dst: &mut BytesMut) -> Result<Bytes> {
+        let message = item.message();
+        if self.use_mask || !self.extensions.is_empty() {
+            self.encode_message(message, dst)?;
+            return Ok(Bytes::new());
+        }
+
+        if self.outgoing_message.is_some() && !message.opcode.is_control() {
+            return Err(
+                ProtocolError::new("can't send a message while another message is being sent in fragments").into(),
+            );
+        }
+
+        self.check_opcode(message.opcode)?;
+
+        #[cfg_attr(not(feature = "deflate"), allow(unused_mut))]
+        let mut header = message.header(None);
+
+        #[cfg_attr(not(feature = "deflate"), allow(unused_mut))]
+        let mut payload = message.data.clone();
+
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &mut self.deflate {
+            if !message.opcode.is_control() && deflate.should_compress(payload.len()) {
+                payload = match deflate.independent_compression() {
+                    Some(settings) => item.compressed(settings, |data| deflate.compress(data))?,
+                    None => deflate.compress(&payload)?.into(),
+                };
+
+                header.rsv |= RSV1;
+                header.data_len = payload.len().into();
+            }
+        }
+
+        let split = match self.max_outgoing_frame_size {
+            Some(max) => payload.len() > max && !message.opcode.is_control(),
+            None => false,
+        };
+
+        if split {
+            self.encode_frames(header, &payload, dst)?;
+            Ok(Bytes::new())
+        } else if header.rsv == 0 {
+            Ok(item.frame().clone())
+        } else {
+            header.write_header_to_bytes(dst);
+            Ok(payload)
+        }
+    }
+
+    // Returns a fresh mask for the next frame, or None if this codec doesn't mask the frames it sends
+    #[cfg(feature = "std")]
+    fn next_mask(&self) -> Option<Mask> {
+        if !self.use_mask {
+            return None;
         }
+
+        let mask = match &self.rng {
+            Some(rng) => rng.lock().unwrap_or_else(PoisonError::into_inner).next_u32().into(),
+            None => Mask::new(),
+        };
+
+        Some(mask)
     }
+
+    // Without std there is no source of random masks, so only codecs that don't mask can be created
+    #[cfg(not(feature = "std"))]
+    #[allow(clippy::unused_self)]
+    fn next_mask(&self) -> Option<Mask> {
+        None
+    }
+
+    fn check_limits(&self, header: &FrameHeader, data_len: usize, progress: Option<MessageProgress>) -> Result<()> {
+        if let Some(max) = self.max_frame_size {
+            if data_len > max {
+                return Err(SizeLimitError::new(SizeLimit::FrameSize, max, data_len).into());
+            }
+        }
+
+        let (message_len, fragments) = match progress {
+            Some(progress) if header.opcode == 0 => (progress.len.saturating_add(data_len), progress.fragments + 1),
+            _ => (data_len, 1),
+        };
+
+        if let Some(max) = self.max_message_size {
+            if message_len > max {
+                return Err(SizeLimitError::new(SizeLimit::MessageSize, max, message_len).into());
+            }
+        }
+
+        if let Some(max) = self.max_fragments {
+            if fragments > max {
+                return Err(SizeLimitError::new(SizeLimit::Fragments, max, fragments).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Rejects reserved opcodes that hold a value outside the reserved range
+fn check_opcode_range(opcode: Opcode) -> Result<()> {
+    if Opcode::try_from(u8::from(opcode)) != Some(opcode) {
+        return Err(ProtocolError::new(format!("{:?} is not a valid opcode", opcode)).into());
+    }
+
+    Ok(())
 }
 
 fn truncate_floor_char_boundary(s: &mut String, new_len: usize) -> usize {
@@ -215,162 +759,435 @@ fn truncate_floor_char_boundary(s: &mut String, new_len: usize) -> usize {
     len
 }
 
-impl Decoder for MessageCodec {
-    type Item = Message;
-    type Error = Error;
+impl MessageCodec {
+    /// Parses the next frame in `src`, checking it against the limits and passing it through any extensions.
+    ///
+    /// The `progress` parameter describes the message that is still waiting for its final frame, if any.
+    pub(crate) fn decode_frame(
+        &mut self,
+        src: &mut BytesMut,
+        progress: Option<MessageProgress>,
+    ) -> Result<Option<DecodedFrame>> {
+        let (header, mut data) = if let Some(frame) =
+            frame::split_frame(src, |header, data_len| self.check_limits(header, data_len, progress))?
+        {
+            frame
+        } else {
+            return Ok(None);
+        };
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
-        let mut state = self.interrupted_message.take();
-        let (opcode, data) = loop {
-            let (header, header_len) = if let Some(tuple) = FrameHeader::parse_slice(src) {
-                tuple
+        let data_len = data.len();
+        let FrameHeader {
+            fin,
+            rsv,
+            opcode,
+            mask,
+            data_len: _data_len,
+        } = header.clone();
+
+        // Masking is only used for client -> server frames, so a client expects unmasked frames and a server expects
+        // masked ones
+        if self.strict_masking && mask.is_some() == self.use_mask {
+            return Err(ProtocolError::new(if mask.is_some() {
+                "frames from the server must not be masked"
             } else {
-                // The buffer isn't big enough for the frame header. Reserve additional space for a frame header,
-                // plus reasonable extensions.
-                src.reserve(512);
-                self.interrupted_message = state;
-                return Ok(None);
-            };
-
-            let data_len = usize::try_from(header.data_len)?;
-            let frame_len = header_len + data_len;
-            if frame_len > src.remaining() {
-                // The buffer contains the frame header but it's not big enough for the data. Reserve additional
-                // space for the frame data, plus the next frame header.
-                // Note that we guard against bad data that indicates an unreasonable frame length.
-
-                // If we reserved buffer space for the entire frame data in a single call, would the buffer exceed
-                // usize::MAX bytes in size?
-                // On a 64-bit platform we should not reach here as the usize::try_from line above enforces the
-                // max payload length detailed in the RFC of 2^63 bytes.
-                if frame_len > usize::MAX - src.remaining() {
-                    return Err(format!("frame is too long: {0} bytes ({0:x})", frame_len).into());
-                }
+                "frames from the client must be masked"
+            })
+            .into());
+        }
 
-                // We don't really reserve space for the entire frame data in a single call. If somebody is sending
-                // more than a gigabyte of data in a single frame then we'll still try to receive it, we'll just
-                // reserve in 1GB chunks.
-                src.reserve(frame_len.min(0x4000_0000) + 512);
+        if let Some(mask) = mask {
+            mask::mask_slice(&mut data, mask);
+        };
 
-                self.interrupted_message = state;
-                return Ok(None);
+        let opcode = if opcode == 0 {
+            None
+        } else {
+            let opcode = Opcode::try_from(opcode)
+                .ok_or_else(|| ProtocolError::new(format!("opcode {} is not supported", opcode)))?;
+            self.check_opcode(opcode)?;
+            if opcode.is_control() && data_len >= 126 {
+                return Err(ProtocolError::new(format!(
+                    "control frames must be shorter than 126 bytes ({} bytes is too long)",
+                    data_len
+                ))
+                .into());
             }
 
-            // The buffer contains the frame header and all of the data. We can parse it and return Ok(Some(...)).
-            let mut data = src.split_to(frame_len);
-            data.advance(header_len);
+            Some(opcode)
+        };
 
-            let FrameHeader {
-                fin,
-                rsv,
-                opcode,
-                mask,
-                data_len: _data_len,
-            } = header;
+        // RSV1 marks the first frame of a compressed message
+        let compressed =
+            rsv & RSV1 != 0 && self.accepts_compressed() && matches!(opcode, Some(opcode) if !opcode.is_control());
 
-            if rsv != 0 {
-                return Err(format!("reserved bits are not supported: 0x{:x}", rsv).into());
+        let mut unclaimed = if compressed { rsv & !RSV1 } else { rsv };
+        for extension in self.extensions.iter_mut().rev() {
+            let claimed = extension.rsv() & unclaimed;
+            if claimed != 0 {
+                extension.decode(&header, &mut data)?;
+                unclaimed &= !claimed;
             }
+        }
 
-            if let Some(mask) = mask {
-                // Note: clients never need decode masked messages because masking is only used for client -> server frames.
-                // However this code is used to test round tripping of masked messages.
-                mask::mask_slice(&mut data, mask);
-            };
+        if unclaimed != 0 {
+            return Err(ProtocolError::new(format!("reserved bits are not supported: 0x{:x}", unclaimed)).into());
+        }
 
-            let opcode = if opcode == 0 {
-                None
-            } else {
-                let opcode = Opcode::try_from(opcode).ok_or_else(|| format!("opcode {} is not supported", opcode))?;
-                if opcode.is_control() && data_len >= 126 {
-                    return Err(format!(
-                        "control frames must be shorter than 126 bytes ({} bytes is too long)",
-                        data_len
-                    )
-                    .into());
-                }
+        Ok(Some(DecodedFrame {
+            fin,
+            opcode,
+            compressed,
+            data,
+        }))
+    }
 
-                Some(opcode)
+    /// Decodes the next message from the front of `src`, joining fragmented messages together.
+    ///
+    /// Returns `None` until `src` holds the message's final frame. This is the same as decoding through the
+    /// `Decoder` trait, which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the peer breaks the WebSocket protocol, sends invalid UTF-8 text or exceeds one of the
+    /// codec's limits.
+    pub fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        let mut state = self.interrupted_message.take();
+        let (opcode, data, compressed, utf8_validated) = loop {
+            let frame = if let Some(frame) = self.decode_frame(src, state.as_ref().map(PartialMessage::progress))? {
+                frame
+            } else {
+                self.interrupted_message = state;
+                return Ok(None);
             };
 
-            state = if let Some((partial_opcode, mut partial_data)) = state {
+            let DecodedFrame {
+                fin,
+                opcode,
+                compressed,
+                data,
+            } = frame;
+
+            state = if let Some(mut partial) = state {
                 if let Some(opcode) = opcode {
                     if fin && opcode.is_control() {
-                        self.interrupted_message = Some((partial_opcode, partial_data));
-                        break (opcode, data);
+                        self.interrupted_message = Some(partial);
+                        break (opcode, data.freeze(), false, false);
                     }
 
-                    return Err(format!("continuation frame must have continuation opcode, not {:?}", opcode).into());
+                    return Err(ProtocolError::new(format!(
+                        "continuation frame must have continuation opcode, not {:?}",
+                        opcode
+                    ))
+                    .into());
                 }
 
-                partial_data.extend_from_slice(&data);
+                if let Some(utf8) = &mut partial.utf8 {
+                    utf8.feed(&data)?;
+                    if fin {
+                        utf8.finish()?;
+                    }
+                }
+
+                partial.push(data);
+                partial.fragments += 1;
 
                 if fin {
-                    break (partial_opcode, partial_data);
+                    let (opcode, compressed, utf8_validated) =
+                        (partial.opcode, partial.compressed, partial.utf8.is_some());
+                    break (opcode, partial.into_data(), compressed, utf8_validated);
                 }
 
-                Some((partial_opcode, partial_data))
+                Some(partial)
             } else if let Some(opcode) = opcode {
                 if fin {
-                    break (opcode, data);
+                    break (opcode, data.freeze(), compressed, false);
                 }
                 if opcode.is_control() {
-                    return Err("control frames must not be fragmented".into());
+                    return Err(ProtocolError::new("control frames must not be fragmented").into());
                 }
-                Some((opcode, data))
+
+                let utf8 = if opcode == Opcode::Text && !compressed {
+                    let mut utf8 = Utf8Validator::default();
+                    utf8.feed(&data)?;
+                    Some(utf8)
+                } else {
+                    None
+                };
+
+                let mut partial = PartialMessage {
+                    opcode,
+                    segments: Vec::new(),
+                    len: 0,
+                    fragments: 1,
+                    compressed,
+                    utf8,
+                };
+
+                partial.push(data);
+                Some(partial)
             } else {
-                return Err("continuation must not be first frame".into());
+                return Err(ProtocolError::new("continuation must not be first frame").into());
+            }
+        };
+
+        let data = if compressed {
+            self.decompress_frame(&data, true, 0)?
+        } else {
+            data
+        };
+
+        if utf8_validated {
+            return Ok(Some(Message { opcode, data }));
+        }
+
+        Ok(Some(Message::new(opcode, data)?))
+    }
+
+    /// Encodes a message into `dst`, splitting it into frames no longer than the maximum outgoing frame size.
+    ///
+    /// This is the same as encoding through the `Encoder` trait, which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the message's opcode is not allowed, if another message is being sent in fragments, or
+    /// if an extension fails to encode the message.
+    pub fn encode_message(&mut self, item: &Message, dst: &mut BytesMut) -> Result<()> {
+        if self.outgoing_message.is_some() && !item.opcode.is_control() {
+            return Err(
+                ProtocolError::new("can't send a message while another message is being sent in fragments").into(),
+            );
+        }
+
+        self.check_opcode(item.opcode)?;
+
+        let mask = self.next_mask();
+
+        #[cfg_attr(not(feature = "deflate"), allow(unused_mut))]
+        let mut header = item.header(mask);
+
+        #[cfg_attr(not(feature = "deflate"), allow(unused_mut))]
+        let mut data = Cow::Borrowed(&item.data[..]);
+
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &mut self.deflate {
+            if !item.opcode.is_control() && deflate.should_compress(data.len()) {
+                data = Cow::Owned(deflate.compress(&data)?);
+                header.rsv |= RSV1;
+            }
+        }
+
+        self.encode_frames(header, &data, dst)
+    }
+
+    /// Encodes one part of a message, as part of a sequence that starts with a [`Fragment::MessageStart`] and ends
+    /// with a [`Fragment::MessageEnd`], or with a [`Fragment::MessageAbort`] if the rest of the message can't be sent.
+    ///
+    /// Each [`Fragment::MessageChunk`] is sent as a frame of its own, and [`Fragment::MessageEnd`] is sent as an empty
+    /// final frame. Messages sent in fragments are never compressed.
+    ///
+    /// This is the same as encoding through the `Encoder` trait, which requires the `std` feature.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if the fragments arrive out of order, if the message's opcode is not allowed, or if the
+    /// chunks of a text message are not valid UTF-8. A message that fails after its [`Fragment::MessageStart`] is
+    /// abandoned, and other messages can be sent afterwards; any chunks already sent leave the peer with an
+    /// incomplete message, so the connection should normally be closed.
+    pub fn encode_fragment(&mut self, item: Fragment, dst: &mut BytesMut) -> Result<()> {
+        let (fin, data) = match item {
+            Fragment::MessageStart { opcode } => {
+                if opcode.is_control() {
+                    return Err(
+                        ProtocolError::new(format!("control frames must not be fragmented, not {:?}", opcode)).into(),
+                    );
+                }
+
+                if self.outgoing_message.is_some() {
+                    return Err(ProtocolError::new(
+                        "can't send a message while another message is being sent in fragments",
+                    )
+                    .into());
+                }
+
+                self.check_opcode(opcode)?;
+
+                self.outgoing_message = Some(OutgoingMessage {
+                    opcode: Some(opcode),
+                    utf8: if opcode == Opcode::Text {
+                        Some(Utf8Validator::default())
+                    } else {
+                        None
+                    },
+                });
+
+                return Ok(());
+            }
+            Fragment::MessageChunk(data) => (false, data),
+            Fragment::MessageEnd => (true, Bytes::new()),
+            Fragment::Control(message) => return self.encode_message(&message, dst),
+            Fragment::MessageAbort => {
+                self.outgoing_message = None;
+                return Ok(());
+            }
+        };
+
+        if self.outgoing_message.is_none() {
+            return Err(ProtocolError::new("message fragment sent before the start of the message").into());
+        }
+
+        // A message that can't be finished is abandoned, so that the codec can go on to send other messages
+        let result = self.encode_chunk(fin, &data, dst);
+        if fin || result.is_err() {
+            self.outgoing_message = None;
+        }
+
+        result
+    }
+
+    // Checks and encodes one chunk of the message that was started by a `Fragment::MessageStart`.
+    fn encode_chunk(&mut self, fin: bool, data: &[u8], dst: &mut BytesMut) -> Result<()> {
+        let outgoing_message = self
+            .outgoing_message
+            .as_mut()
+            .expect("message fragment sent before the start of the message");
+
+        if let Some(utf8) = &mut outgoing_message.utf8 {
+            utf8.feed(data)?;
+            if fin {
+                utf8.finish()?;
             }
+        }
+
+        let opcode = outgoing_message.opcode.take();
+        let header = FrameHeader {
+            fin,
+            rsv: 0,
+            opcode: opcode.map_or(0, u8::from),
+            mask: self.next_mask(),
+            data_len: data.len().into(),
         };
 
-        Ok(Some(Message::new(opcode, data.freeze())?))
+        self.encode_frames(header, data, dst)
+    }
+
+    // Splits a payload into frames no longer than the maximum outgoing frame size, then writes them to `dst`.
+    fn encode_frames(&mut self, mut header: FrameHeader, data: &[u8], dst: &mut BytesMut) -> Result<()> {
+        // Control frames have opcodes 0x8 and above, and must not be fragmented
+        let max = match self.max_outgoing_frame_size {
+            Some(max) if data.len() > max && header.opcode & 0x8 == 0 => max,
+            _ => return self.encode_payload(header, data, dst),
+        };
+
+        let fin = header.fin;
+        let mut chunks = data.chunks(max).peekable();
+        while let Some(chunk) = chunks.next() {
+            header.fin = fin && chunks.peek().is_none();
+            self.encode_payload(header.clone(), chunk, dst)?;
+
+            // Only the first frame carries the opcode and RSV1, and each frame gets a fresh mask
+            header.rsv = 0;
+            header.opcode = 0;
+            header.mask = self.next_mask();
+        }
+
+        Ok(())
+    }
+
+    // Passes one frame through any extensions, then writes it to `dst`.
+    fn encode_payload(&mut self, mut header: FrameHeader, data: &[u8], dst: &mut BytesMut) -> Result<()> {
+        if self.extensions.is_empty() {
+            header.data_len = data.len().into();
+            frame::encode_frame(&header, data, dst);
+        } else {
+            let mut data = BytesMut::from(data);
+            for extension in &mut self.extensions {
+                header.rsv |= extension.encode(&header, &mut data)? & extension.rsv();
+            }
+
+            header.data_len = data.len().into();
+            frame::encode_frame(&header, &data, dst);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        self.decode_message(src)
     }
 }
 
+#[cfg(feature = "std")]
 impl Encoder<Message> for MessageCodec {
     type Error = Error;
 
     fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<()> {
+        self.encode_message(&item, dst)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Encoder<PreparedMessage> for MessageCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: PreparedMessage, dst: &mut BytesMut) -> Result<()> {
         self.encode(&item, dst)
     }
 }
 
-impl<'a> Encoder<&'a Message> for MessageCodec {
+#[cfg(feature = "std")]
+impl<'a> Encoder<&'a PreparedMessage> for MessageCodec {
     type Error = Error;
 
-    fn encode(&mut self, item: &Message, dst: &mut BytesMut) -> Result<()> {
-        let mask = if self.use_mask { Some(Mask::new()) } else { None };
-        let header = item.header(mask);
-        header.write_to_bytes(dst);
+    fn encode(&mut self, item: &'a PreparedMessage, dst: &mut BytesMut) -> Result<()> {
+        let frame = self.encode_prepared(item, dst)?;
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
 
-        if let Some(mask) = mask {
-            let offset = dst.len();
-            dst.reserve(item.data.len());
+#[cfg(feature = "std")]
+impl<'a> Encoder<&'a Message> for MessageCodec {
+    type Error = Error;
 
-            unsafe {
-                dst.set_len(offset + item.data.len());
-            }
+    fn encode(&mut self, item: &Message, dst: &mut BytesMut) -> Result<()> {
+        self.encode_message(item, dst)
+    }
+}
 
-            mask::mask_slice_copy(&mut dst[offset..], &item.data, mask);
-        } else {
-            dst.put_slice(&item.data);
-        }
+#[cfg(feature = "std")]
+impl Encoder<Fragment> for MessageCodec {
+    type Error = Error;
 
-        Ok(())
+    fn encode(&mut self, item: Fragment, dst: &mut BytesMut) -> Result<()> {
+        self.encode_fragment(item, dst)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
     use assert_allocations::assert_allocated_bytes;
-    use bytes::{BufMut, BytesMut};
+    use bytes::{BufMut, Bytes, BytesMut};
     use quickcheck::{Arbitrary, Gen};
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
     use tokio_util::codec::{Decoder, Encoder};
 
+    use crate::close::CloseCode;
     use crate::frame::{FrameHeader, FrameHeaderCodec};
+    use crate::limit::{SizeLimit, SizeLimitError};
     use crate::mask;
     use crate::mask::Mask;
     use crate::message::{Message, MessageCodec};
+    use crate::opcode::Opcode;
 
     #[derive(Clone, Debug)]
     enum MessageInput {
@@ -461,7 +1278,7 @@ mod tests {
         let mut src = bytes.split();
 
         let message2 = assert_allocated_bytes(0, || {
-            MessageCodec::client()
+            MessageCodec::server()
                 .decode(&mut src)
                 .expect("didn't expect MessageCodec::decode to return an error")
                 .expect("expected buffer to contain the full frame")
@@ -500,8 +1317,9 @@ mod tests {
         // instead of inside the assert_allocated_bytes(0) block below.
         let mut src = bytes.split();
 
+        // The frame may or may not be masked, so decode it without checking which endpoint sent it
         assert_allocated_bytes(0, || {
-            let message2 = MessageCodec::client()
+            let message2 = MessageCodec::with_masked_encode(false)
                 .decode(&mut src)
                 .expect("didn't expect MessageCodec::decode to return an error")
                 .expect("expected buffer to contain the full frame");
@@ -529,7 +1347,7 @@ mod tests {
         // allocations as decoder_buf is resized multiple times.
 
         let mut src = &bytes[..];
-        let mut decoder = MessageCodec::client();
+        let mut decoder = MessageCodec::server();
         let mut decoder_buf = BytesMut::new();
         let message2 = loop {
             if let Some(result) = decoder
@@ -585,16 +1403,560 @@ mod tests {
         );
     }
 
+    fn frame(fin: bool, opcode: u8, data: &[u8]) -> BytesMut {
+        let header = FrameHeader {
+            fin,
+            rsv: 0,
+            opcode,
+            mask: None,
+            data_len: data.len().into(),
+        };
+
+        let mut bytes = BytesMut::new();
+        FrameHeaderCodec.encode(&header, &mut bytes).unwrap();
+        bytes.put_slice(data);
+        bytes
+    }
+
+    fn size_limit_error(err: &crate::Error) -> &SizeLimitError {
+        if let crate::Error::Size(err) = err {
+            err
+        } else {
+            panic!("expected SizeLimitError, got {:?}", err)
+        }
+    }
+
+    #[test]
+    fn max_frame_size_rejects_header_without_reserving() {
+        // Header only: a binary frame claiming a 1GB payload
+        let data: &[u8] = &[0x82, 127, 0, 0, 0, 0, 0x40, 0, 0, 0];
+        let mut src = BytesMut::from(data);
+
+        let err = MessageCodec::server()
+            .with_max_frame_size(1024)
+            .decode(&mut src)
+            .expect_err("expected decoder to reject a frame over the limit");
+
+        let err = size_limit_error(&err);
+        assert_eq!(err.limit(), SizeLimit::FrameSize);
+        assert_eq!(err.max(), 1024);
+        assert_eq!(err.actual(), 0x4000_0000);
+        assert_eq!(err.close_code(), CloseCode::Size);
+        assert!(src.capacity() < 1024);
+    }
+
+    #[test]
+    fn max_message_size_counts_all_fragments() {
+        let mut codec = MessageCodec::client().with_max_message_size(10);
+        let mut src = frame(false, 2, b"12345");
+        src.unsplit(frame(true, 0, b"67890"));
+        assert_eq!(
+            codec.decode(&mut src).unwrap().unwrap(),
+            Message::binary(&b"1234567890"[..])
+        );
+
+        let mut src = frame(false, 2, b"12345");
+        src.unsplit(frame(false, 0, b"67890"));
+        src.unsplit(frame(true, 0, b"!"));
+        let err = codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject a message over the limit");
+        let err = size_limit_error(&err);
+        assert_eq!(err.limit(), SizeLimit::MessageSize);
+        assert_eq!(err.actual(), 11);
+    }
+
+    #[test]
+    fn max_fragments_ignores_control_frames() {
+        let mut codec = MessageCodec::client().with_max_fragments(2);
+        let mut src = frame(false, 1, b"a");
+        src.unsplit(frame(true, 9, b"ping"));
+        src.unsplit(frame(true, 0, b"b"));
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap(), Message::ping(&b"ping"[..]));
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap(), Message::text("ab"));
+
+        let mut src = frame(false, 1, b"a");
+        src.unsplit(frame(false, 0, b"b"));
+        src.unsplit(frame(true, 0, b"c"));
+        let err = codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject a message over the limit");
+        let err = size_limit_error(&err);
+        assert_eq!(err.limit(), SizeLimit::Fragments);
+        assert_eq!(err.actual(), 3);
+    }
+
+    #[test]
+    fn reassembles_fragments_across_reads() {
+        let mut codec = MessageCodec::client();
+        let mut src = frame(false, 2, b"abc");
+        src.unsplit(frame(false, 0, b""));
+        src.unsplit(frame(false, 0, b"def"));
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+        // Reuse the read buffer, as a Framed does, while the decoder holds on to the earlier fragments
+        src.clear();
+        src.unsplit(frame(true, 0, b"gh"));
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Message::binary(&b"abcdefgh"[..])));
+    }
+
+    #[test]
+    fn validates_text_as_fragments_arrive() {
+        let text = "caf\u{e9} \u{1f600}".as_bytes();
+        let mut codec = MessageCodec::client();
+        let mut src = frame(false, 1, &text[..4]);
+        src.unsplit(frame(false, 0, &text[4..7]));
+        src.unsplit(frame(true, 0, &text[7..]));
+        assert_eq!(
+            codec.decode(&mut src).unwrap().unwrap(),
+            Message::text("caf\u{e9} \u{1f600}")
+        );
+
+        // The final frame never arrives, but the invalid text is rejected anyway
+        let mut src = frame(false, 1, b"ok");
+        src.unsplit(frame(
+            false,
+            0,
+            b"\xce\xba\xe1\xbd\xb9\xcf\x83\xce\xbc\xce\xb5\xed\xa0\x80",
+        ));
+        let err = codec
+            .decode(&mut src)
+            .expect_err("expected decoder to reject invalid UTF-8 before the final frame");
+        assert_eq!(err.close_code(), Some(CloseCode::Invalid));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn round_trips_compressed_messages() {
+        use crate::deflate::DeflateConfig;
+
+        let config = DeflateConfig::new();
+        let mut client = MessageCodec::client().with_deflate(config.clone());
+        let mut server = MessageCodec::server().with_deflate(config);
+        let text = "Hello, world! ".repeat(100);
+
+        let mut buf = BytesMut::new();
+        client.encode(Message::text(text.clone()), &mut buf).unwrap();
+        assert_eq!(buf[0], 0xc1, "expected FIN, RSV1 and the text opcode");
+        assert!(buf.len() < text.len() / 10);
+
+        let compressed = buf.clone();
+        client.encode(Message::text("Hi"), &mut buf).unwrap();
+        client.encode(Message::ping(text[..100].to_owned()), &mut buf).unwrap();
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::text(text.clone()));
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::text("Hi"));
+        assert_eq!(
+            server.decode(&mut buf).unwrap().unwrap(),
+            Message::ping(text[..100].to_owned())
+        );
+
+        MessageCodec::server()
+            .decode(&mut compressed.clone())
+            .expect_err("expected decoder to reject RSV1 without deflate");
+
+        let err = MessageCodec::server()
+            .with_deflate(DeflateConfig::new())
+            .with_max_message_size(100)
+            .decode(&mut compressed.clone())
+            .expect_err("expected decoder to reject a decompressed message over the limit");
+
+        assert_eq!(size_limit_error(&err).limit(), SizeLimit::MessageSize);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn limits_decompressed_size_by_default() {
+        use crate::deflate::DeflateConfig;
+
+        // A message that compresses to about 64KB, but decompresses to 1MB more than the default limit
+        let mut buf = BytesMut::new();
+        MessageCodec::client()
+            .with_deflate(DeflateConfig::new())
+            .encode(Message::binary(vec![0; 65 * 1024 * 1024]), &mut buf)
+            .unwrap();
+        assert!(buf.len() < 100_000);
+
+        let err = MessageCodec::server()
+            .with_deflate(DeflateConfig::new())
+            .decode(&mut buf)
+            .expect_err("expected decoder to reject a decompressed message over the default limit");
+
+        let err = size_limit_error(&err);
+        assert_eq!(err.limit(), SizeLimit::MessageSize);
+        assert_eq!(err.max(), 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn round_trips_with_extension() {
+        use crate::extension::tests::Checksum;
+
+        let mut client = MessageCodec::client().with_extension(Box::new(Checksum));
+        let mut server = MessageCodec::server().with_extension(Box::new(Checksum));
+
+        let mut buf = BytesMut::new();
+        client.encode(Message::text("Hello"), &mut buf).unwrap();
+        client.encode(Message::ping("ping"), &mut buf).unwrap();
+        assert_eq!(buf[0], 0xa1, "expected FIN, RSV2 and the text opcode");
+        assert_eq!(buf[1] & 0x7f, 6, "expected payload to include the checksum");
+
+        let sent = buf.clone();
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::text("Hello"));
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::ping("ping"));
+
+        MessageCodec::server()
+            .decode(&mut sent.clone())
+            .expect_err("expected decoder to reject RSV2 without the extension");
+
+        let mut src = frame(true, 1, b"Hello!");
+        src[0] |= 0x20;
+        server
+            .decode(&mut src)
+            .expect_err("expected extension to reject a bad checksum");
+    }
+
+    #[test]
+    #[should_panic(expected = "already in use")]
+    fn rejects_extensions_claiming_the_same_rsv_bits() {
+        use crate::extension::tests::Checksum;
+
+        let _ = MessageCodec::server()
+            .with_extension(Box::new(Checksum))
+            .with_extension(Box::new(Checksum));
+    }
+
+    #[test]
+    fn round_trips_message_sent_in_fragments() {
+        use crate::fragment::Fragment;
+
+        let mut client = MessageCodec::client().with_extension(Box::new(crate::extension::tests::Checksum));
+        let mut server = MessageCodec::server().with_extension(Box::new(crate::extension::tests::Checksum));
+        let mut buf = BytesMut::new();
+        client
+            .encode(Fragment::MessageStart { opcode: Opcode::Text }, &mut buf)
+            .unwrap();
+        assert!(buf.is_empty());
+
+        client
+            .encode(Fragment::MessageChunk(Bytes::from_static(b"caf\xc3")), &mut buf)
+            .unwrap();
+        assert_eq!(buf[0], 0x21, "expected RSV2 and the text opcode, without FIN");
+
+        client
+            .encode(Fragment::Control(Message::ping("ping")), &mut buf)
+            .unwrap();
+        client
+            .encode(&Message::text("interrupted"), &mut buf)
+            .expect_err("expected encoder to reject a message between fragments");
+
+        client
+            .encode(Fragment::MessageChunk(Bytes::from_static(b"\xa9")), &mut buf)
+            .unwrap();
+        client.encode(Fragment::MessageEnd, &mut buf).unwrap();
+        client.encode(Message::text("next"), &mut buf).unwrap();
+
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::ping("ping"));
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::text("caf\u{e9}"));
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::text("next"));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_text_sent_in_fragments() {
+        use crate::fragment::Fragment;
+
+        let mut codec = MessageCodec::server();
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Fragment::MessageChunk(Bytes::from_static(b"abc")), &mut buf)
+            .expect_err("expected encoder to reject a chunk before the start of the message");
+
+        codec
+            .encode(Fragment::MessageStart { opcode: Opcode::Text }, &mut buf)
+            .unwrap();
+        codec
+            .encode(Fragment::MessageChunk(Bytes::from_static(b"\xc3")), &mut buf)
+            .unwrap();
+        codec
+            .encode(Fragment::MessageEnd, &mut buf)
+            .expect_err("expected encoder to reject incomplete UTF-8");
+
+        // Each failure abandons the message, leaving the codec free to send the next one
+        codec.encode(Message::text("next"), &mut buf).unwrap();
+        codec
+            .encode(Fragment::MessageStart { opcode: Opcode::Text }, &mut buf)
+            .unwrap();
+        codec
+            .encode(Fragment::MessageChunk(Bytes::from_static(b"\xff")), &mut buf)
+            .expect_err("expected encoder to reject invalid UTF-8");
+        codec
+            .encode(Fragment::MessageEnd, &mut buf)
+            .expect_err("expected encoder to reject the end of an abandoned message");
+
+        // A message can also be abandoned explicitly
+        codec
+            .encode(Fragment::MessageStart { opcode: Opcode::Binary }, &mut buf)
+            .unwrap();
+        codec.encode(Fragment::MessageAbort, &mut buf).unwrap();
+
+        buf.clear();
+        codec.encode(Message::text("next"), &mut buf).unwrap();
+        assert_eq!(
+            MessageCodec::client().decode(&mut buf).unwrap(),
+            Some(Message::text("next"))
+        );
+    }
+
+    #[test]
+    fn splits_messages_over_max_outgoing_frame_size() {
+        let mut client = MessageCodec::client().with_max_outgoing_frame_size(4);
+        let mut buf = BytesMut::new();
+        client.encode(Message::binary(&b"0123456789"[..]), &mut buf).unwrap();
+        client.encode(Message::ping(&b"ping!"[..]), &mut buf).unwrap();
+
+        let mut frames = Vec::new();
+        let mut src = buf.clone();
+        while let Some(header) = FrameHeaderCodec.decode(&mut src).unwrap() {
+            let data_len = usize::try_from(header.data_len()).unwrap();
+            let mut data = src.split_to(data_len);
+            mask::mask_slice(&mut data, header.mask().unwrap());
+            frames.push((header.fin(), header.opcode(), data));
+        }
+
+        assert_eq!(
+            frames,
+            vec![
+                (false, 2, BytesMut::from(&b"0123"[..])),
+                (false, 0, BytesMut::from(&b"4567"[..])),
+                (true, 0, BytesMut::from(&b"89"[..])),
+                (true, 9, BytesMut::from(&b"ping!"[..])),
+            ]
+        );
+
+        let mut server = MessageCodec::server();
+        assert_eq!(
+            server.decode(&mut buf).unwrap().unwrap(),
+            Message::binary(&b"0123456789"[..])
+        );
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap(), Message::ping(&b"ping!"[..]));
+    }
+
+    #[test]
+    fn masks_owned_message_in_place() {
+        let data = BytesMut::from(&b"Hello, world"[..]);
+        let ptr = data.as_ptr();
+        let mut buf = BytesMut::new();
+        let payload = MessageCodec::client()
+            .encode_owned(Message::binary_mut(data), &mut buf)
+            .unwrap();
+
+        assert_eq!(payload.as_ptr(), ptr, "expected the payload to be masked in place");
+        assert_eq!(
+            buf.len(),
+            6,
+            "expected only the frame header to be written to the buffer"
+        );
+
+        buf.extend_from_slice(&payload);
+        assert_eq!(
+            MessageCodec::server().decode(&mut buf).unwrap(),
+            Some(Message::binary(&b"Hello, world"[..]))
+        );
+
+        // Only space for the header is reserved, however long the payload
+        for mut codec in [MessageCodec::client(), MessageCodec::server()] {
+            let mut buf = BytesMut::new();
+            let data = BytesMut::from(&vec![0x55; 1024 * 1024][..]);
+            codec.encode_owned(Message::binary_mut(data), &mut buf).unwrap();
+            assert!(
+                buf.capacity() < 1024,
+                "expected a small buffer, got {} bytes",
+                buf.capacity()
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_shared_or_split_messages_in_full() {
+        let data = Bytes::from_static(b"Hello, world");
+        let mut buf = BytesMut::new();
+        let payload = MessageCodec::client()
+            .encode_owned(Message::binary(data.clone()), &mut buf)
+            .unwrap();
+
+        assert!(payload.is_empty());
+        assert_eq!(
+            MessageCodec::server().decode(&mut buf).unwrap(),
+            Some(Message::binary(data.clone()))
+        );
+
+        let payload = MessageCodec::client()
+            .with_max_outgoing_frame_size(5)
+            .encode_owned(Message::binary_mut(BytesMut::from(&data[..])), &mut buf)
+            .unwrap();
+
+        assert!(payload.is_empty());
+        assert_eq!(
+            MessageCodec::server().decode(&mut buf).unwrap(),
+            Some(Message::binary(data))
+        );
+    }
+
+    #[test]
+    fn rejects_reserved_opcodes_unless_allowed() {
+        let mut src = frame(true, 3, b"data");
+        src.unsplit(frame(true, 0xb, b"control"));
+        MessageCodec::client()
+            .decode(&mut src.clone())
+            .expect_err("expected decoder to reject a reserved opcode");
+
+        let mut codec = MessageCodec::client().with_reserved_opcodes(true);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Message::new(Opcode::ReservedData(3), &b"data"[..]).unwrap())
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Message::new(Opcode::ReservedControl(0xb), &b"control"[..]).unwrap())
+        );
+
+        let message = Message::new(Opcode::ReservedData(3), &b"data"[..]).unwrap();
+        MessageCodec::client()
+            .encode(&message, &mut BytesMut::new())
+            .expect_err("expected encoder to reject a reserved opcode");
+
+        Message::new(Opcode::ReservedData(8), Bytes::new()).expect_err("expected 8 to be outside the reserved range");
+    }
+
+    #[test]
+    fn allows_reserved_opcodes_defined_by_extension() {
+        #[derive(Clone)]
+        struct Opcodes;
+
+        impl crate::Extension for Opcodes {
+            fn name(&self) -> &str {
+                "x-opcodes"
+            }
+
+            fn rsv(&self) -> u8 {
+                0x10
+            }
+
+            fn reserved_opcodes(&self) -> &[u8] {
+                &[4]
+            }
+
+            fn encode(&mut self, _header: &FrameHeader, _data: &mut BytesMut) -> crate::Result<u8> {
+                Ok(0)
+            }
+
+            fn decode(&mut self, _header: &FrameHeader, _data: &mut BytesMut) -> crate::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut client = MessageCodec::client().with_extension(Box::new(Opcodes));
+        let mut server = MessageCodec::server().with_extension(Box::new(Opcodes));
+        let message = Message::new(Opcode::ReservedData(4), &b"data"[..]).unwrap();
+        let mut buf = BytesMut::new();
+        client.encode(&message, &mut buf).unwrap();
+        assert_eq!(server.decode(&mut buf).unwrap(), Some(message));
+
+        let message = Message::new(Opcode::ReservedData(5), &b"data"[..]).unwrap();
+        client
+            .encode(&message, &mut buf)
+            .expect_err("expected encoder to reject an opcode the extension doesn't define");
+    }
+
     #[test]
     fn roundtrips_multiple_messages() {
         // According to https://docs.rs/tokio-util/0.7.3/tokio_util/codec/index.html#the-encoder-trait
         // the buffer given to the Encoder may already contain data.
         // Therefore, we check whether writing two messages into the same buffer roundtrips correctly.
         let mut buf = BytesMut::new();
-        let mut codec = MessageCodec::server();
-        codec.encode(Message::text("A"), &mut buf).unwrap();
-        codec.encode(Message::text("B"), &mut buf).unwrap();
-        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), Message::text("A"));
-        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), Message::text("B"));
+        let mut server = MessageCodec::server();
+        server.encode(Message::text("A"), &mut buf).unwrap();
+        server.encode(Message::text("B"), &mut buf).unwrap();
+
+        let mut client = MessageCodec::client();
+        assert_eq!(client.decode(&mut buf).unwrap().unwrap(), Message::text("A"));
+        assert_eq!(client.decode(&mut buf).unwrap().unwrap(), Message::text("B"));
+    }
+
+    #[test]
+    fn enforces_masking_direction() {
+        let mut masked = BytesMut::new();
+        MessageCodec::client().encode(Message::text("A"), &mut masked).unwrap();
+
+        let mut unmasked = BytesMut::new();
+        MessageCodec::server()
+            .encode(Message::text("A"), &mut unmasked)
+            .unwrap();
+
+        let err = MessageCodec::client()
+            .decode(&mut masked.clone())
+            .expect_err("expected client to reject a masked frame");
+        assert_eq!(err.close_code(), Some(CloseCode::Protocol));
+
+        let err = MessageCodec::server()
+            .decode(&mut unmasked.clone())
+            .expect_err("expected server to reject an unmasked frame");
+        assert_eq!(err.close_code(), Some(CloseCode::Protocol));
+
+        let mut lenient = MessageCodec::with_masked_encode(false);
+        assert_eq!(lenient.decode(&mut masked).unwrap(), Some(Message::text("A")));
+        assert_eq!(lenient.decode(&mut unmasked).unwrap(), Some(Message::text("A")));
+    }
+
+    #[test]
+    fn exposes_text_without_copying() {
+        static HELLO: &str = "Hello, world";
+        let message = Message::text_utf8(HELLO);
+        assert_eq!(message.data().as_ptr(), HELLO.as_ptr());
+        assert_eq!(Message::text(HELLO), message);
+
+        let text = message.to_text().unwrap();
+        assert_eq!(text.slice(7..), "world");
+        assert_eq!(text.as_ptr(), HELLO.as_ptr());
+        assert_eq!(message.into_text().unwrap(), text);
+        assert_eq!(Message::binary(HELLO.as_bytes()).to_text(), None);
+    }
+
+    #[test]
+    fn creates_text_from_strings() {
+        use std::borrow::Cow;
+
+        let owned = String::from("Hello");
+        let borrowed: &str = &owned;
+        for message in [
+            Message::text(&owned),
+            Message::text(borrowed),
+            Message::text(owned.clone().into_boxed_str()),
+            Message::text(Cow::Borrowed(borrowed)),
+            Message::text_utf8(owned.clone()),
+        ] {
+            assert_eq!(message, Message::text("Hello"));
+        }
+    }
+
+    #[test]
+    fn seeded_rng_makes_masks_reproducible() {
+        let encode = || {
+            let mut codec = MessageCodec::client().with_rng(StdRng::seed_from_u64(42));
+            let mut buf = BytesMut::new();
+            codec.encode(Message::text("Hello"), &mut buf).unwrap();
+            codec.clone().encode(Message::text("world"), &mut buf).unwrap();
+            buf
+        };
+
+        let mut buf = encode();
+        assert_eq!(buf, encode());
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..2 {
+            let header = FrameHeaderCodec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(header.mask(), Some(Mask::from(rng.next_u32())));
+            let data_len = usize::try_from(header.data_len()).unwrap();
+            let _ = buf.split_to(data_len);
+        }
     }
 }
@@ -11,6 +11,16 @@ pub enum Opcode {
     Ping,
     /// Pong control frame.
     Pong,
+    /// One of the data opcodes reserved for future use, `0x3` to `0x7`.
+    ///
+    /// [`MessageCodec`](crate::MessageCodec) rejects frames with this opcode unless an extension or
+    /// [`MessageCodec::with_reserved_opcodes`](crate::MessageCodec::with_reserved_opcodes) allows them.
+    ReservedData(u8),
+    /// One of the control opcodes reserved for future use, `0xB` to `0xF`.
+    ///
+    /// [`MessageCodec`](crate::MessageCodec) rejects frames with this opcode unless an extension or
+    /// [`MessageCodec::with_reserved_opcodes`](crate::MessageCodec::with_reserved_opcodes) allows them.
+    ReservedControl(u8),
 }
 
 impl Opcode {
@@ -20,23 +30,31 @@ impl Opcode {
         matches!(self, Self::Text)
     }
 
-    /// Returns `true` if `self` is `Close`, `Ping` or `Pong`.
+    /// Returns `true` if `self` is `Close`, `Ping`, `Pong` or `ReservedControl`.
     #[must_use]
     pub fn is_control(self) -> bool {
-        matches!(self, Self::Close | Self::Ping | Self::Pong)
+        matches!(self, Self::Close | Self::Ping | Self::Pong | Self::ReservedControl(_))
+    }
+
+    /// Returns `true` if `self` is `ReservedData` or `ReservedControl`.
+    #[must_use]
+    pub fn is_reserved(self) -> bool {
+        matches!(self, Self::ReservedData(_) | Self::ReservedControl(_))
     }
 
     /// Converts `u8` to `Opcode`.
     ///
-    /// Returns `None` for unrecognised and unsupported opcodes.
+    /// Returns `None` for the continuation opcode, `0x0`, and for values that don't fit in the 4-bit opcode field.
     #[must_use]
     pub fn try_from(data: u8) -> Option<Self> {
         let opcode = match data {
             1 => Self::Text,
             2 => Self::Binary,
+            3..=7 => Self::ReservedData(data),
             8 => Self::Close,
             9 => Self::Ping,
             10 => Self::Pong,
+            11..=15 => Self::ReservedControl(data),
             _ => {
                 return None;
             }
@@ -54,6 +72,24 @@ impl From<Opcode> for u8 {
             Opcode::Close => 8,
             Opcode::Ping => 9,
             Opcode::Pong => 10,
+            Opcode::ReservedData(data) | Opcode::ReservedControl(data) => data,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::opcode::Opcode;
+
+    #[test]
+    fn round_trips_every_opcode() {
+        for data in 1..16 {
+            let opcode = Opcode::try_from(data).unwrap();
+            assert_eq!(u8::from(opcode), data);
+            assert_eq!(opcode.is_control(), data >= 8);
+        }
+
+        assert_eq!(Opcode::try_from(0), None);
+        assert_eq!(Opcode::try_from(16), None);
+    }
+}
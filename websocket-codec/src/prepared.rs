@@ -0,0 +1,164 @@
+#[cfg(feature = "deflate")]
+use alloc::vec::Vec;
+#[cfg(feature = "deflate")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::frame;
+use crate::message::Message;
+#[cfg(feature = "deflate")]
+use crate::Result;
+
+// The window bits and compression level that decide the compressed form of a message
+#[cfg(feature = "deflate")]
+type CompressionSettings = (u8, u32);
+
+/// A message that has been encoded once, so that it can be sent to many peers without being encoded again.
+///
+/// Servers don't mask the frames they send, so a message sent by one server connection is identical byte-for-byte to
+/// the same message sent by any other. A `PreparedMessage` holds that frame in a shared [`Bytes`] buffer. Cloning it
+/// is cheap, and [`MessageCodec::encode_prepared`](crate::MessageCodec::encode_prepared) returns the frame without
+/// copying it.
+///
+/// When permessage-deflate is in use, and the server compresses each message independently of the ones before it, the
+/// compressed form of the message is also kept, so that the message is compressed once for all connections that agreed
+/// the same compression parameters. Clones of a `PreparedMessage` share these compressed forms.
+#[derive(Clone, Debug)]
+pub struct PreparedMessage {
+    message: Message,
+    // The message as a single unmasked, uncompressed frame
+    frame: Bytes,
+    // The compressed payload for each combination of window bits and compression level used so far
+    #[cfg(feature = "deflate")]
+    compressed: Arc<Mutex<Vec<(CompressionSettings, Bytes)>>>,
+}
+
+impl PreparedMessage {
+    /// Encodes `message` as a single unmasked frame.
+    #[must_use]
+    pub fn new(message: Message) -> Self {
+        let header = message.header(None);
+        let mut frame = BytesMut::with_capacity(header.header_len() + message.data().len());
+        frame::encode_frame(&header, message.data(), &mut frame);
+
+        Self {
+            message,
+            frame: frame.freeze(),
+            #[cfg(feature = "deflate")]
+            compressed: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the message that was prepared.
+    #[must_use]
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Returns the message encoded as a single unmasked, uncompressed frame.
+    #[must_use]
+    pub fn frame(&self) -> &Bytes {
+        &self.frame
+    }
+
+    // Returns the compressed payload for the given window bits and compression level, calling `compress` only the
+    // first time that these settings are used
+    #[cfg(feature = "deflate")]
+    pub(crate) fn compressed<F>(&self, settings: CompressionSettings, compress: F) -> Result<Bytes>
+    where
+        F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+    {
+        // Holding the lock while compressing means that other connections wait for this result instead of repeating it
+        let mut compressed = self.compressed.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, payload)) = compressed.iter().find(|(s, _)| *s == settings) {
+            return Ok(payload.clone());
+        }
+
+        let payload = Bytes::from(compress(self.message.data())?);
+        compressed.push((settings, payload.clone()));
+        Ok(payload)
+    }
+}
+
+impl From<Message> for PreparedMessage {
+    fn from(message: Message) -> Self {
+        Self::new(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::message::{Message, MessageCodec};
+    use crate::prepared::PreparedMessage;
+
+    #[test]
+    fn server_sends_prepared_frame() {
+        let prepared = PreparedMessage::new(Message::binary(&b"Hello, world"[..]));
+        let mut expected = BytesMut::new();
+        MessageCodec::server()
+            .encode(prepared.message(), &mut expected)
+            .unwrap();
+
+        for mut codec in [
+            MessageCodec::server(),
+            MessageCodec::server().with_max_outgoing_frame_size(20),
+        ] {
+            let mut dst = BytesMut::new();
+            let frame = codec.encode_prepared(&prepared, &mut dst).unwrap();
+            assert!(dst.is_empty());
+            assert_eq!(frame, expected);
+            assert_eq!(
+                frame.as_ptr(),
+                prepared.frame().as_ptr(),
+                "expected the frame to be shared"
+            );
+        }
+
+        // Masked and fragmented messages are encoded in full
+        for mut codec in [
+            MessageCodec::client(),
+            MessageCodec::server().with_max_outgoing_frame_size(5),
+        ] {
+            let mut dst = BytesMut::new();
+            assert!(codec.encode_prepared(&prepared, &mut dst).unwrap().is_empty());
+            assert_eq!(
+                MessageCodec::with_masked_encode(false).decode(&mut dst).unwrap(),
+                Some(prepared.message().clone())
+            );
+        }
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn compresses_once_without_context_takeover() {
+        use crate::deflate::DeflateConfig;
+
+        let text = "Hello, world! ".repeat(100);
+        let prepared = PreparedMessage::new(Message::text(text.clone()));
+        let shared = DeflateConfig::new().with_server_no_context_takeover(true);
+        let mut payloads = Vec::new();
+        for config in [shared.clone(), shared, DeflateConfig::new()] {
+            let mut server = MessageCodec::server().with_deflate(config.clone());
+            let mut client = MessageCodec::client().with_deflate(config);
+            for _ in 0..2 {
+                let mut dst = BytesMut::new();
+                let payload = server.encode_prepared(&prepared, &mut dst).unwrap();
+                assert_eq!(dst[0], 0xc1, "expected FIN, RSV1 and the text opcode");
+                assert!(dst.capacity() < payload.len(), "expected space for the header only");
+                payloads.push(payload.clone());
+
+                dst.extend_from_slice(&payload);
+                assert_eq!(client.decode(&mut dst).unwrap(), Some(Message::text(text.clone())));
+            }
+        }
+
+        // The first two connections share one compressed payload, while the third uses its own context
+        assert!(payloads[..4].iter().all(|p| p.as_ptr() == payloads[0].as_ptr()));
+        assert!(payloads[4].len() < text.len() / 10);
+        assert!(payloads[5].len() < payloads[4].len());
+    }
+}
@@ -2,4 +2,7 @@
 //!
 //! See [RFC6455 "The WebSocket Protocol"](https://tools.ietf.org/html/rfc6455) for a detailed definition of the fields
 //! in the frame header and their relation to the overall WebSocket protocol.
-pub use crate::frame::{DataLength, FrameHeader, FrameHeaderCodec};
+#[cfg(feature = "std")]
+pub use crate::frame::FrameHeaderCodec;
+pub use crate::frame::{DataLength, Frame, FrameCodec, FrameHeader};
+pub use crate::mask::Mask;
@@ -1,16 +1,32 @@
 use std::fmt::Write;
-use std::{result, str};
+use std::str;
 
 use base64::display::Base64Display;
-use bytes::{Buf, BytesMut};
-use httparse::{Header, Response};
+use bytes::{Buf, BufMut, BytesMut};
+use httparse::{Header, Request, Response, Status};
 use sha1::Sha1;
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::{Error, Result};
+#[cfg(feature = "deflate")]
+use crate::deflate::{self, DeflateConfig};
+use crate::extension::Extension;
+use crate::header::{self as http_header, ExtensionHeader};
+use crate::{Error, HandshakeError, Result};
 
 type Sha1Digest = [u8; sha1::DIGEST_LENGTH];
 
+// The number of headers that the decoders make room for at first, and the most that they accept
+const INITIAL_HEADERS: usize = 32;
+const MAX_HEADERS: usize = 1024;
+
+// The request headers read by `ClientRequest` that hold comma-separated lists, which may be split across several lines
+const LIST_HEADERS: [&str; 4] = [
+    "Connection",
+    "Upgrade",
+    "Sec-WebSocket-Protocol",
+    "Sec-WebSocket-Extensions",
+];
+
 fn build_ws_accept(key: &str) -> Sha1Digest {
     let mut s = Sha1::new();
     s.update(key.as_bytes());
@@ -18,24 +34,38 @@ fn build_ws_accept(key: &str) -> Sha1Digest {
     s.digest().bytes()
 }
 
-fn header<'a, 'header: 'a>(headers: &'a [Header<'header>], name: &'a str) -> result::Result<&'header [u8], String> {
+fn header<'a, 'header: 'a>(headers: &'a [Header<'header>], name: &'a str) -> Result<&'header [u8]> {
     let header = headers
         .iter()
         .find(|header| header.name.eq_ignore_ascii_case(name))
-        .ok_or_else(|| format!("server didn't respond with {name} header", name = name))?;
+        .ok_or_else(|| HandshakeError::new(format!("server didn't respond with {name} header", name = name)))?;
 
     Ok(header.value)
 }
 
-fn validate_server_response(expected_ws_accept: &Sha1Digest, data: &[u8]) -> Result<Option<usize>> {
-    let mut headers = [httparse::EMPTY_HEADER; 20];
-    let mut response = Response::new(&mut headers);
-    let status = response.parse(data)?;
-    if !status.is_complete() {
-        return Ok(None);
+fn extensions_header(headers: &[Header<'_>]) -> Result<Option<String>> {
+    let mut extensions: Option<String> = None;
+    for header in headers {
+        if header.name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+            let value = str::from_utf8(header.value).map_err(|e| {
+                HandshakeError::new(format!(
+                    "server responded with invalid Sec-WebSocket-Extensions header: {}",
+                    e
+                ))
+            })?;
+            if let Some(extensions) = &mut extensions {
+                *extensions += ", ";
+                *extensions += value;
+            } else {
+                extensions = Some(value.to_owned());
+            }
+        }
     }
 
-    let response_len = status.unwrap();
+    Ok(extensions)
+}
+
+fn validate_server_response(expected_ws_accept: &Sha1Digest, response: &Response<'_, '_>) -> Result<Option<String>> {
     let code = response.code.unwrap();
     if code != 101 {
         let mut error_message = format!("server responded with HTTP error {code}", code = code);
@@ -44,92 +74,163 @@ fn validate_server_response(expected_ws_accept: &Sha1Digest, data: &[u8]) -> Res
             write!(error_message, ": {:?}", reason).expect("formatting reason failed");
         }
 
-        return Err(error_message.into());
+        return Err(HandshakeError::new(error_message).into());
     }
 
     let ws_accept_header = header(response.headers, "Sec-WebSocket-Accept")?;
     let mut ws_accept = Sha1Digest::default();
-    base64::decode_config_slice(&ws_accept_header, base64::STANDARD, &mut ws_accept)?;
+    base64::decode_config_slice(&ws_accept_header, base64::STANDARD, &mut ws_accept).map_err(|e| {
+        HandshakeError::new(format!(
+            "server responded with invalid Sec-WebSocket-Accept header: {}",
+            e
+        ))
+    })?;
+
     if expected_ws_accept != &ws_accept {
-        return Err(format!(
+        return Err(HandshakeError::new(format!(
             "server responded with incorrect Sec-WebSocket-Accept header: expected {expected}, got {actual}",
             expected = Base64Display::with_config(expected_ws_accept, base64::STANDARD),
             actual = Base64Display::with_config(&ws_accept, base64::STANDARD),
-        )
+        ))
         .into());
     }
 
-    Ok(Some(response_len))
+    extensions_header(response.headers)
 }
 
-fn contains_ignore_ascii_case(mut haystack: &[u8], needle: &[u8]) -> bool {
-    if needle.is_empty() {
-        return true;
+// Joins the values of every header with the given name, as allowed for headers that hold comma-separated lists
+fn joined_header(headers: &[Header<'_>], name: &str) -> Option<String> {
+    let mut values = headers
+        .iter()
+        .filter(|header| header.name.eq_ignore_ascii_case(name))
+        .filter_map(|header| str::from_utf8(header.value).ok());
+
+    let first = values.next()?;
+    Some(values.fold(first.to_owned(), |joined, value| joined + ", " + value))
+}
+
+// Checks that a header name is an HTTP token and that its value doesn't end the header early
+fn check_header(name: &str, value: &str) -> Result<()> {
+    let is_tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    if name.is_empty() || !name.bytes().all(is_tchar) {
+        return Err(HandshakeError::new(format!("invalid header name: {:?}", name)).into());
     }
 
-    while haystack.len() >= needle.len() {
-        if haystack[..needle.len()].eq_ignore_ascii_case(needle) {
-            return true;
-        }
+    if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+        return Err(HandshakeError::new(format!("invalid value for header {}: {:?}", name, value)).into());
+    }
+
+    Ok(())
+}
+
+// Writes the header lines of an HTTP message, followed by the blank line that ends the headers
+fn write_headers(headers: &[(&str, &str)], dst: &mut BytesMut) -> Result<()> {
+    for &(name, value) in headers {
+        check_header(name, value)?;
+    }
 
-        haystack = &haystack[1..];
+    for &(name, value) in headers {
+        dst.put_slice(name.as_bytes());
+        dst.put_slice(b": ");
+        dst.put_slice(value.as_bytes());
+        dst.put_slice(b"\r\n");
     }
 
-    false
+    dst.put_slice(b"\r\n");
+    Ok(())
 }
 
 /// A client's opening handshake.
+#[derive(Clone, Debug)]
 pub struct ClientRequest {
+    key: String,
     ws_accept: Sha1Digest,
+    protocols: Vec<String>,
+    extensions: Option<String>,
+    origin: Option<String>,
 }
 
 impl ClientRequest {
     /// Parses the client's opening handshake.
     ///
+    /// The `header` function returns the value of the header with the given name, ignoring case. For headers that
+    /// hold comma-separated lists, such as `Connection` and `Sec-WebSocket-Protocol`, it should join the values of
+    /// all headers with that name, separated by commas.
+    ///
     /// # Errors
     ///
-    /// This method fails when a header required for the WebSocket protocol is missing in the handshake.
+    /// This method fails when a header required for the WebSocket protocol is missing in the handshake, or when the
+    /// `Sec-WebSocket-Key` header does not hold 16 bytes encoded in base64.
     pub fn parse<'a, F>(header: F) -> Result<Self>
     where
         F: Fn(&'static str) -> Option<&'a str> + 'a,
     {
-        let header = |name| header(name).ok_or_else(|| format!("client didn't provide {name} header", name = name));
+        let header = |name| {
+            header(name).ok_or_else(|| HandshakeError::new(format!("client didn't provide {name} header", name = name)))
+        };
 
         let check_header = |name, expected| {
             let actual = header(name)?;
             if actual.eq_ignore_ascii_case(expected) {
                 Ok(())
             } else {
-                Err(format!(
+                Err(HandshakeError::new(format!(
                     "client provided incorrect {name} header: expected {expected}, got {actual}",
                     name = name,
                     expected = expected,
                     actual = actual
-                ))
+                )))
             }
         };
 
         let check_header_contains = |name, expected: &str| {
             let actual = header(name)?;
-            if contains_ignore_ascii_case(actual.as_bytes(), expected.as_bytes()) {
+            if http_header::parse_tokens(actual).any(|token| token.eq_ignore_ascii_case(expected)) {
                 Ok(())
             } else {
-                Err(format!(
-                    "client provided incorrect {name} header: expected string containing {expected}, got {actual}",
+                Err(HandshakeError::new(format!(
+                    "client provided incorrect {name} header: expected list containing {expected}, got {actual}",
                     name = name,
                     expected = expected,
                     actual = actual
-                ))
+                )))
             }
         };
 
-        check_header("Upgrade", "websocket")?;
+        check_header_contains("Upgrade", "websocket")?;
         check_header_contains("Connection", "Upgrade")?;
         check_header("Sec-WebSocket-Version", "13")?;
 
         let key = header("Sec-WebSocket-Key")?;
-        let ws_accept = build_ws_accept(key);
-        Ok(Self { ws_accept })
+        match base64::decode_config(key, base64::STANDARD) {
+            Ok(nonce) if nonce.len() == 16 => {}
+            _ => {
+                return Err(HandshakeError::new(format!(
+                    "client provided invalid Sec-WebSocket-Key header: expected 16 bytes encoded in base64, got {}",
+                    key
+                ))
+                .into());
+            }
+        }
+
+        let protocols = header("Sec-WebSocket-Protocol").map_or_else(
+            |_| Vec::new(),
+            |protocols| http_header::parse_tokens(protocols).map(ToOwned::to_owned).collect(),
+        );
+
+        Ok(Self {
+            key: key.to_owned(),
+            ws_accept: build_ws_accept(key),
+            protocols,
+            extensions: header("Sec-WebSocket-Extensions").ok().map(ToOwned::to_owned),
+            origin: header("Origin").ok().map(ToOwned::to_owned),
+        })
+    }
+
+    /// Returns the value of the client's `Sec-WebSocket-Key` header.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
     }
 
     /// Copies the value that the client expects to see in the server's `Sec-WebSocket-Accept` header into a `String`.
@@ -142,63 +243,1009 @@ impl ClientRequest {
     pub fn ws_accept(&self) -> String {
         base64::encode_config(&self.ws_accept, base64::STANDARD)
     }
+
+    /// Returns the subprotocols offered by the client in its `Sec-WebSocket-Protocol` header, in the client's order
+    /// of preference.
+    ///
+    /// The slice is empty if the client didn't offer any subprotocols. To select one, pass it to
+    /// [`UpgradeResponse::with_protocol`].
+    #[must_use]
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Returns the extensions offered by the client in its `Sec-WebSocket-Extensions` header, if any.
+    #[must_use]
+    pub fn extensions(&self) -> Option<&str> {
+        self.extensions.as_deref()
+    }
+
+    /// Returns the value of the client's `Origin` header, if any.
+    ///
+    /// Browsers send the origin of the page that opened the connection. Servers can check it to refuse connections
+    /// from pages on other sites.
+    #[must_use]
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Chooses the first of the client's offers of `extension` that the extension accepts.
+    ///
+    /// Returns the value to be sent in the server's `Sec-WebSocket-Extensions` response header, or `None` if the
+    /// client did not offer the extension with parameters that it can accept. When this method returns `Some`, pass
+    /// `extension` to [`MessageCodec::with_extension`](crate::MessageCodec::with_extension).
+    pub fn accept_extension(&self, extension: &mut dyn Extension) -> Option<String> {
+        let offers = http_header::parse_extensions(self.extensions.as_deref()?).ok()?;
+        let name = extension.name().to_owned();
+        offers
+            .into_iter()
+            .filter(|offer| offer.name == name)
+            .find_map(|offer| extension.accept_offer(&offer.params))
+    }
+}
+
+/// A client's HTTP upgrade request, as decoded by [`ServerUpgradeCodec`].
+#[derive(Clone, Debug)]
+pub struct UpgradeRequest {
+    path: String,
+    headers: Vec<(String, Vec<u8>)>,
+    client_request: ClientRequest,
+}
+
+impl UpgradeRequest {
+    fn parse(request: &Request<'_, '_>) -> Result<Self> {
+        let method = request.method.unwrap_or_default();
+        if method != "GET" {
+            return Err(HandshakeError::new(format!("client sent an HTTP {} request: expected GET", method)).into());
+        }
+
+        if request.version != Some(1) {
+            return Err(HandshakeError::new("client sent an HTTP/1.0 request: expected HTTP/1.1").into());
+        }
+
+        // Accept an absolute path, or an absolute URL as sent through a proxy
+        let path = request.path.unwrap_or_default();
+        if !path.starts_with('/') && !path.contains("://") {
+            return Err(HandshakeError::new(format!("client requested an invalid path: {:?}", path)).into());
+        }
+
+        let lists = LIST_HEADERS
+            .iter()
+            .map(|&name| (name, joined_header(request.headers, name)))
+            .collect::<Vec<_>>();
+
+        let client_request = ClientRequest::parse(|name| {
+            if let Some((_, value)) = lists.iter().find(|(list_name, _)| *list_name == name) {
+                return value.as_deref();
+            }
+
+            request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case(name))
+                .and_then(|header| str::from_utf8(header.value).ok())
+        })?;
+
+        let headers = request
+            .headers
+            .iter()
+            .map(|header| (header.name.to_owned(), header.value.to_owned()))
+            .collect();
+
+        Ok(Self {
+            path: path.to_owned(),
+            headers,
+            client_request,
+        })
+    }
+
+    /// Returns the path requested by the client, including any query string.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the headers of the client's request, in the order that the client sent them.
+    #[must_use]
+    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header in the client's request with the given name, ignoring case.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the WebSocket-specific parts of the client's request.
+    #[must_use]
+    pub fn client_request(&self) -> &ClientRequest {
+        &self.client_request
+    }
+}
+
+/// The server's `101 Switching Protocols` response to a client's opening handshake, as encoded by
+/// [`ServerUpgradeCodec`].
+#[derive(Clone, Debug)]
+pub struct UpgradeResponse {
+    ws_accept: String,
+    protocol: Option<String>,
+    extensions: Option<String>,
+    headers: Vec<(String, String)>,
 }
 
-/// Tokio decoder for parsing the server's response to the client's HTTP `Connection: Upgrade` request.
+impl UpgradeResponse {
+    /// Returns a response that accepts `request`, without selecting a subprotocol or any extensions.
+    #[must_use]
+    pub fn new(request: &ClientRequest) -> Self {
+        Self {
+            ws_accept: request.ws_accept(),
+            protocol: None,
+            extensions: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Sets the subprotocol selected by the server, sent in the `Sec-WebSocket-Protocol` header.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: String) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Sets the extensions accepted by the server, sent in the `Sec-WebSocket-Extensions` header.
+    ///
+    /// The value is typically built from the strings returned by [`ClientRequest::accept_extension`] and
+    /// [`DeflateConfig::accept_offer`](crate::DeflateConfig::accept_offer), separated by commas.
+    #[must_use]
+    pub fn with_extensions(mut self, extensions: String) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    /// Adds an extra HTTP header to the response.
+    #[must_use]
+    pub fn with_header(mut self, name: String, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+/// Tokio codec for the server side of the opening handshake, for accepting WebSocket connections without an HTTP
+/// server library.
+///
+/// The decoder parses the client's HTTP `GET` request, checking that it asks for a WebSocket upgrade, and returns it
+/// as an [`UpgradeRequest`]. The encoder writes the server's [`UpgradeResponse`]. Once the response has been sent,
+/// replace the codec with [`MessageCodec::server`](crate::MessageCodec::server), configured with any extensions that
+/// the response accepted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServerUpgradeCodec;
+
+impl Decoder for ServerUpgradeCodec {
+    type Item = UpgradeRequest;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<UpgradeRequest>> {
+        let mut header_count = INITIAL_HEADERS;
+        loop {
+            let mut headers = vec![httparse::EMPTY_HEADER; header_count];
+            let mut request = Request::new(&mut headers);
+            match request.parse(src) {
+                Ok(Status::Complete(request_len)) => {
+                    let request = UpgradeRequest::parse(&request)?;
+                    src.advance(request_len);
+                    return Ok(Some(request));
+                }
+                Ok(Status::Partial) => return Ok(None),
+                Err(httparse::Error::TooManyHeaders) if header_count < MAX_HEADERS => header_count *= 2,
+                Err(e) => {
+                    return Err(HandshakeError::new(format!("client sent an invalid HTTP request: {}", e)).into());
+                }
+            }
+        }
+    }
+}
+
+impl Encoder<UpgradeResponse> for ServerUpgradeCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: UpgradeResponse, dst: &mut BytesMut) -> Result<()> {
+        self.encode(&item, dst)
+    }
+}
+
+impl<'a> Encoder<&'a UpgradeResponse> for ServerUpgradeCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &'a UpgradeResponse, dst: &mut BytesMut) -> Result<()> {
+        let mut headers = vec![
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Accept", item.ws_accept.as_str()),
+        ];
+
+        headers.extend(
+            item.protocol
+                .as_deref()
+                .map(|protocol| ("Sec-WebSocket-Protocol", protocol)),
+        );
+        headers.extend(
+            item.extensions
+                .as_deref()
+                .map(|extensions| ("Sec-WebSocket-Extensions", extensions)),
+        );
+        headers.extend(item.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+
+        dst.put_slice(b"HTTP/1.1 101 Switching Protocols\r\n");
+        write_headers(&headers, dst)
+    }
+}
+
+/// The client's HTTP upgrade request, as encoded by [`UpgradeCodec`].
+///
+/// The codec adds the headers that the WebSocket protocol requires, along with the subprotocols and extensions that
+/// the codec offers.
+#[derive(Clone, Debug)]
+pub struct HandshakeRequest {
+    host: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl HandshakeRequest {
+    /// Returns a request for `path`, including any query string, sent to the server named in the `Host` header.
+    ///
+    /// The `host` parameter holds the server's host name, followed by a colon and the port number unless the port is
+    /// the default for the URL scheme.
+    #[must_use]
+    pub fn new(host: String, path: String) -> Self {
+        Self {
+            host,
+            path,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Adds an extra HTTP header to the request.
+    #[must_use]
+    pub fn with_header(mut self, name: String, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+/// The server's response to the client's opening handshake, as decoded by [`UpgradeCodec`].
+#[derive(Clone, Debug)]
+pub struct HandshakeResponse {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    protocol: Option<String>,
+    extensions: Option<String>,
+}
+
+impl HandshakeResponse {
+    /// Returns the HTTP status code of the server's response, which is always 101 for a successful handshake.
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the headers of the server's response, in the order that the server sent them.
+    #[must_use]
+    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header in the server's response with the given name, ignoring case.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the subprotocol selected by the server in its `Sec-WebSocket-Protocol` header, if any.
+    #[must_use]
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Returns the extensions accepted by the server in its `Sec-WebSocket-Extensions` headers, if any.
+    ///
+    /// When the server sends the header more than once, the values are joined with commas.
+    #[must_use]
+    pub fn extensions(&self) -> Option<&str> {
+        self.extensions.as_deref()
+    }
+
+    /// Returns the value of each `Set-Cookie` header in the server's response.
+    pub fn set_cookies(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Set-Cookie"))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+/// Tokio codec for the client side of the opening handshake.
+///
+/// The encoder writes the client's HTTP `Connection: Upgrade` request, described by a [`HandshakeRequest`]. The
+/// decoder parses the server's response, checking its `Sec-WebSocket-Accept` header and the subprotocol and
+/// extensions that the server selected, and returns it as a [`HandshakeResponse`].
 pub struct UpgradeCodec {
+    key: String,
     ws_accept: Sha1Digest,
+    protocol_offers: Vec<String>,
+    #[cfg(feature = "deflate")]
+    deflate_offer: Option<DeflateConfig>,
+    #[cfg(feature = "deflate")]
+    deflate: Option<DeflateConfig>,
+    extension_offers: Vec<Box<dyn Extension>>,
+    extensions: Vec<Box<dyn Extension>>,
 }
 
 impl UpgradeCodec {
     /// Returns a new `UpgradeCodec` object.
     ///
-    /// The `key` parameter provides the string passed to the server via the HTTP `Sec-WebSocket-Key` header.
+    /// The `key` parameter provides the string that the encoder sends to the server in the HTTP `Sec-WebSocket-Key`
+    /// header.
     #[must_use]
     pub fn new(key: &str) -> Self {
         UpgradeCodec {
+            key: key.to_owned(),
             ws_accept: build_ws_accept(key),
+            protocol_offers: Vec::new(),
+            #[cfg(feature = "deflate")]
+            deflate_offer: None,
+            #[cfg(feature = "deflate")]
+            deflate: None,
+            extension_offers: Vec::new(),
+            extensions: Vec::new(),
         }
     }
+
+    /// Offers a subprotocol to the server, in the HTTP `Sec-WebSocket-Protocol` header.
+    ///
+    /// Subprotocols are offered in the order in which they are added. The decoder rejects a response that selects a
+    /// subprotocol that was not offered.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: String) -> Self {
+        self.protocol_offers.push(protocol);
+        self
+    }
+
+    /// Validates the server's response to an offer of the permessage-deflate extension.
+    ///
+    /// The `offer` parameter provides the parameters passed to the server via the HTTP `Sec-WebSocket-Extensions`
+    /// header, as returned by [`DeflateConfig::offer`]. Once the response has been decoded, [`UpgradeCodec::deflate`]
+    /// returns the parameters agreed by the server.
+    #[cfg(feature = "deflate")]
+    #[must_use]
+    pub fn with_deflate(mut self, offer: DeflateConfig) -> Self {
+        self.deflate_offer = Some(offer);
+        self
+    }
+
+    /// Returns the permessage-deflate parameters agreed by the server, or `None` if the server declined compression.
+    ///
+    /// This method returns `None` until the server's response has been decoded.
+    #[cfg(feature = "deflate")]
+    #[must_use]
+    pub fn deflate(&self) -> Option<&DeflateConfig> {
+        self.deflate.as_ref()
+    }
+
+    /// Validates the server's response to an offer of `extension`.
+    ///
+    /// The client offers the extension to the server via the HTTP `Sec-WebSocket-Extensions` header, as returned by
+    /// [`UpgradeCodec::extensions_offer`]. Once the response has been decoded, [`UpgradeCodec::extensions`] returns
+    /// the extensions accepted by the server.
+    #[must_use]
+    pub fn with_extension(mut self, extension: Box<dyn Extension>) -> Self {
+        self.extension_offers.push(extension);
+        self
+    }
+
+    /// Returns the value of the client's `Sec-WebSocket-Extensions` request header, listing the extensions passed to
+    /// [`UpgradeCodec::with_extension`], or `None` if there are no extensions to offer.
+    #[must_use]
+    pub fn extensions_offer(&self) -> Option<String> {
+        let mut offers = Vec::new();
+
+        #[cfg(feature = "deflate")]
+        offers.extend(self.deflate_offer.as_ref().map(DeflateConfig::offer));
+
+        offers.extend(self.extension_offers.iter().map(|extension| extension.offer()));
+
+        if offers.is_empty() {
+            None
+        } else {
+            Some(offers.join(", "))
+        }
+    }
+
+    /// Returns the extensions accepted by the server, in the order listed in the server's response.
+    ///
+    /// This method returns an empty slice until the server's response has been decoded.
+    #[must_use]
+    pub fn extensions(&self) -> &[Box<dyn Extension>] {
+        &self.extensions
+    }
+
+    #[cfg(feature = "deflate")]
+    fn accept_deflate(&mut self, extension: &ExtensionHeader<'_>) -> Result<Option<u8>> {
+        if extension.name != deflate::EXTENSION_NAME {
+            return Ok(None);
+        }
+
+        if let Some(offer) = &self.deflate_offer {
+            if self.deflate.is_none() {
+                self.deflate = Some(offer.accept_response_params(&extension.params)?);
+                return Ok(Some(crate::frame::RSV1));
+            }
+        }
+
+        Ok(None)
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    #[allow(clippy::unused_self, clippy::unnecessary_wraps)]
+    fn accept_deflate(&mut self, _extension: &ExtensionHeader<'_>) -> Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn accept_protocol(&self, response: &Response<'_, '_>) -> Result<Option<String>> {
+        let protocol = match joined_header(response.headers, "Sec-WebSocket-Protocol") {
+            Some(protocol) => protocol,
+            None => return Ok(None),
+        };
+
+        if !self.protocol_offers.contains(&protocol) {
+            return Err(HandshakeError::new(format!(
+                "server selected a subprotocol that the client did not offer: {}",
+                protocol
+            ))
+            .into());
+        }
+
+        Ok(Some(protocol))
+    }
+
+    fn accept_extensions(&mut self, response: Option<&str>) -> Result<()> {
+        let response = if let Some(response) = response {
+            response
+        } else {
+            return Ok(());
+        };
+
+        let mut claimed = 0;
+        for extension in http_header::parse_extensions(response)? {
+            let rsv = if let Some(rsv) = self.accept_deflate(&extension)? {
+                rsv
+            } else {
+                let index = self
+                    .extension_offers
+                    .iter()
+                    .position(|offer| offer.name() == extension.name)
+                    .ok_or_else(|| {
+                        HandshakeError::new(format!(
+                            "server accepted an extension that the client did not offer: {}",
+                            extension.name
+                        ))
+                    })?;
+
+                let mut agreed = self.extension_offers.remove(index);
+                agreed.accept_response(&extension.params)?;
+
+                let rsv = agreed.rsv();
+                self.extensions.push(agreed);
+                rsv
+            };
+
+            if rsv & claimed != 0 {
+                return Err(HandshakeError::new(format!(
+                    "server accepted extensions that claim the same RSV bits: 0x{:x}",
+                    rsv & claimed
+                ))
+                .into());
+            }
+
+            claimed |= rsv;
+        }
+
+        Ok(())
+    }
+}
+
+impl UpgradeCodec {
+    fn accept_response(&mut self, response: &Response<'_, '_>) -> Result<HandshakeResponse> {
+        let extensions = validate_server_response(&self.ws_accept, response)?;
+        let protocol = self.accept_protocol(response)?;
+        self.accept_extensions(extensions.as_deref())?;
+
+        let headers = response
+            .headers
+            .iter()
+            .map(|header| (header.name.to_owned(), header.value.to_owned()))
+            .collect();
+
+        Ok(HandshakeResponse {
+            status: response.code.unwrap_or_default(),
+            headers,
+            protocol,
+            extensions,
+        })
+    }
 }
 
 impl Decoder for UpgradeCodec {
-    type Item = ();
+    type Item = HandshakeResponse;
     type Error = Error;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<()>> {
-        if let Some(response_len) = validate_server_response(&self.ws_accept, src)? {
-            src.advance(response_len);
-            Ok(Some(()))
-        } else {
-            Ok(None)
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<HandshakeResponse>> {
+        let mut header_count = INITIAL_HEADERS;
+        loop {
+            let mut headers = vec![httparse::EMPTY_HEADER; header_count];
+            let mut response = Response::new(&mut headers);
+            match response.parse(src) {
+                Ok(Status::Complete(response_len)) => {
+                    let response = self.accept_response(&response).map_err(|e| match e {
+                        Error::Handshake(e) => Error::Handshake(e.with_response(&response)),
+                        e => e,
+                    })?;
+
+                    src.advance(response_len);
+                    return Ok(Some(response));
+                }
+                Ok(Status::Partial) => return Ok(None),
+                Err(httparse::Error::TooManyHeaders) if header_count < MAX_HEADERS => header_count *= 2,
+                Err(e) => {
+                    return Err(HandshakeError::new(format!("server sent an invalid HTTP response: {}", e)).into());
+                }
+            }
         }
     }
 }
 
-impl Encoder<()> for UpgradeCodec {
+impl Encoder<HandshakeRequest> for UpgradeCodec {
     type Error = Error;
 
-    fn encode(&mut self, _item: (), _dst: &mut BytesMut) -> Result<()> {
-        unimplemented!()
+    fn encode(&mut self, item: HandshakeRequest, dst: &mut BytesMut) -> Result<()> {
+        self.encode(&item, dst)
+    }
+}
+
+impl<'a> Encoder<&'a HandshakeRequest> for UpgradeCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &'a HandshakeRequest, dst: &mut BytesMut) -> Result<()> {
+        if item.path.is_empty()
+            || item
+                .path
+                .bytes()
+                .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return Err(HandshakeError::new(format!("invalid request path: {:?}", item.path)).into());
+        }
+
+        let protocols = self.protocol_offers.join(", ");
+        let extensions = self.extensions_offer();
+        let mut headers = vec![
+            ("Host", item.host.as_str()),
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", self.key.as_str()),
+            ("Sec-WebSocket-Version", "13"),
+        ];
+
+        if !protocols.is_empty() {
+            headers.push(("Sec-WebSocket-Protocol", &protocols));
+        }
+
+        headers.extend(
+            extensions
+                .as_deref()
+                .map(|extensions| ("Sec-WebSocket-Extensions", extensions)),
+        );
+        headers.extend(item.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+
+        dst.put_slice(b"GET ");
+        dst.put_slice(item.path.as_bytes());
+        dst.put_slice(b" HTTP/1.1\r\n");
+        write_headers(&headers, dst)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::upgrade::contains_ignore_ascii_case;
+    #[test]
+    fn reports_status_and_headers_of_failed_handshake() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::upgrade::UpgradeCodec;
+        use crate::Error;
+
+        let mut src = BytesMut::from(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Retry-After: 30\r\n\
+             \r\n",
+        );
+
+        let err = UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==")
+            .decode(&mut src)
+            .expect_err("expected decoder to reject an HTTP error");
+
+        let err = if let Error::Handshake(err) = err {
+            err
+        } else {
+            panic!("expected HandshakeError, got {:?}", err)
+        };
+
+        assert_eq!(err.status(), Some(503));
+        assert_eq!(err.header("Retry-After"), Some(&b"30"[..]));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn decodes_agreed_deflate_parameters() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::deflate::DeflateConfig;
+        use crate::upgrade::UpgradeCodec;
+
+        let mut src = BytesMut::from(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits=10\r\n\
+             \r\n",
+        );
+
+        let mut codec = UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==").with_deflate(DeflateConfig::new());
+        codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(src.len(), 0);
+        assert_eq!(codec.deflate().unwrap().client_max_window_bits(), 10);
+    }
+
+    #[test]
+    fn decodes_agreed_extensions() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::extension::tests::Checksum;
+        use crate::upgrade::UpgradeCodec;
+
+        let response = |extensions| {
+            BytesMut::from(
+                format!(
+                    "HTTP/1.1 101 Switching Protocols\r\n\
+                     Upgrade: websocket\r\n\
+                     Connection: Upgrade\r\n\
+                     Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+                     Sec-WebSocket-Extensions: {}\r\n\
+                     \r\n",
+                    extensions
+                )
+                .as_str(),
+            )
+        };
+
+        let codec = || UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==").with_extension(Box::new(Checksum));
+        assert_eq!(codec().extensions_offer().as_deref(), Some("x-checksum"));
+
+        let mut accepted = codec();
+        accepted.decode(&mut response("x-checksum")).unwrap().unwrap();
+        assert_eq!(accepted.extensions().len(), 1);
+        assert_eq!(accepted.extensions()[0].name(), "x-checksum");
+
+        codec()
+            .decode(&mut response("x-checksum; foo=1"))
+            .expect_err("expected decoder to reject an unknown parameter");
+
+        codec()
+            .decode(&mut response("x-other"))
+            .expect_err("expected decoder to reject an extension that was not offered");
+
+        codec()
+            .decode(&mut response("x-checksum, x-checksum"))
+            .expect_err("expected decoder to reject an extension accepted twice");
+    }
 
     #[test]
-    fn does_not_contain() {
-        assert!(!contains_ignore_ascii_case(b"World", b"hello"));
+    fn encodes_request() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Encoder;
+
+        use crate::extension::tests::Checksum;
+        use crate::upgrade::{HandshakeRequest, UpgradeCodec};
+
+        let mut codec = UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==")
+            .with_protocol("chat".to_owned())
+            .with_protocol("superchat".to_owned())
+            .with_extension(Box::new(Checksum));
+
+        let request = HandshakeRequest::new("server.example.com".to_owned(), "/chat?room=1".to_owned())
+            .with_header("Origin".to_owned(), "http://example.com".to_owned());
+
+        let mut dst = BytesMut::new();
+        codec.encode(&request, &mut dst).unwrap();
+        assert_eq!(
+            dst,
+            "GET /chat?room=1 HTTP/1.1\r\n\
+             Host: server.example.com\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: chat, superchat\r\n\
+             Sec-WebSocket-Extensions: x-checksum\r\n\
+             Origin: http://example.com\r\n\
+             \r\n"
+        );
+
+        codec
+            .encode(
+                HandshakeRequest::new(
+                    "server.example.com".to_owned(),
+                    "/chat HTTP/1.1\r\nX-Injected: 1".to_owned(),
+                ),
+                &mut BytesMut::new(),
+            )
+            .expect_err("expected encoder to reject a path with spaces and line breaks");
     }
 
     #[test]
-    fn contains_exact() {
-        assert!(contains_ignore_ascii_case(b"Hello", b"hello"));
+    fn decodes_handshake_response() {
+        use std::fmt::Write;
+
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::upgrade::UpgradeCodec;
+
+        let response = |protocol| {
+            let mut response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+                 Sec-WebSocket-Protocol: {}\r\n\
+                 Set-Cookie: a=1\r\n\
+                 Set-Cookie: b=2\r\n",
+                protocol
+            );
+
+            // More headers than the decoder makes room for at first
+            for i in 0..40 {
+                write!(response, "X-Filler-{}: {}\r\n", i, i).unwrap();
+            }
+
+            response += "\r\n";
+            BytesMut::from(response.as_str())
+        };
+
+        let codec = || UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==").with_protocol("chat".to_owned());
+        let decoded = codec().decode(&mut response("chat")).unwrap().unwrap();
+        assert_eq!(decoded.status(), 101);
+        assert_eq!(decoded.headers().len(), 46);
+        assert_eq!(decoded.header("x-filler-39"), Some(&b"39"[..]));
+        assert_eq!(decoded.protocol(), Some("chat"));
+        assert_eq!(decoded.extensions(), None);
+        assert_eq!(
+            decoded.set_cookies().collect::<Vec<_>>(),
+            vec![&b"a=1"[..], &b"b=2"[..]]
+        );
+
+        codec()
+            .decode(&mut response("superchat"))
+            .expect_err("expected decoder to reject a subprotocol that was not offered");
     }
 
     #[test]
-    fn contains_substring() {
-        assert!(contains_ignore_ascii_case(b"Hello World", b"hello"));
+    fn parses_client_request() {
+        use crate::upgrade::ClientRequest;
+
+        let parse = |connection, key| {
+            ClientRequest::parse(move |name| match name {
+                "Upgrade" => Some("WebSocket"),
+                "Connection" => Some(connection),
+                "Sec-WebSocket-Version" => Some("13"),
+                "Sec-WebSocket-Key" => Some(key),
+                "Sec-WebSocket-Protocol" => Some("chat, superchat"),
+                "Origin" => Some("http://example.com"),
+                _ => None,
+            })
+        };
+
+        let req = parse("keep-alive, Upgrade", "dGhlIHNhbXBsZSBub25jZQ==").unwrap();
+        assert_eq!(req.key(), "dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(req.ws_accept(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(req.protocols(), ["chat", "superchat"]);
+        assert_eq!(req.extensions(), None);
+        assert_eq!(req.origin(), Some("http://example.com"));
+
+        for (connection, key) in [
+            ("keep-alive, Upgraded", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Upgrade", "dGhlIHNhbXBsZSBub25j"),
+            ("Upgrade", "dGhlIHNhbXBsZSBub25jZSE="),
+            ("Upgrade", "not base64"),
+        ] {
+            parse(connection, key).expect_err(&format!(
+                "expected parser to reject Connection: {} and Sec-WebSocket-Key: {}",
+                connection, key
+            ));
+        }
+    }
+
+    #[test]
+    fn server_accepts_extension() {
+        use crate::extension::tests::Checksum;
+        use crate::upgrade::ClientRequest;
+
+        let request = |extensions| {
+            ClientRequest::parse(move |name| match name {
+                "Upgrade" => Some("websocket"),
+                "Connection" => Some("Upgrade"),
+                "Sec-WebSocket-Version" => Some("13"),
+                "Sec-WebSocket-Key" => Some("dGhlIHNhbXBsZSBub25jZQ=="),
+                "Sec-WebSocket-Extensions" => extensions,
+                _ => None,
+            })
+            .unwrap()
+        };
+
+        let req = request(Some("x-checksum; foo, permessage-deflate, x-checksum"));
+        assert_eq!(req.accept_extension(&mut Checksum).as_deref(), Some("x-checksum"));
+        assert_eq!(request(Some("x-checksum; foo")).accept_extension(&mut Checksum), None);
+        assert_eq!(request(None).accept_extension(&mut Checksum), None);
+    }
+
+    #[test]
+    fn server_decodes_upgrade_request() {
+        use std::fmt::Write;
+
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::upgrade::ServerUpgradeCodec;
+
+        let mut request = String::from(
+            "GET /chat?room=1 HTTP/1.1\r\n\
+             Host: server.example.com\r\n\
+             Upgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Extensions: permessage-deflate\r\n\
+             Sec-WebSocket-Extensions: x-checksum\r\n",
+        );
+
+        // More headers than the decoder makes room for at first
+        for i in 0..40 {
+            write!(request, "X-Filler-{}: {}\r\n", i, i).unwrap();
+        }
+
+        request += "\r\n\x01";
+
+        let mut codec = ServerUpgradeCodec;
+        let mut src = BytesMut::from(&request[..100]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&request.as_bytes()[100..]);
+        let request = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(
+            &src[..],
+            b"\x01",
+            "expected data after the request to be left in the buffer"
+        );
+        assert_eq!(request.path(), "/chat?room=1");
+        assert_eq!(request.header("host"), Some(&b"server.example.com"[..]));
+        assert_eq!(request.headers().len(), 47);
+        assert_eq!(request.client_request().ws_accept(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(
+            request.client_request().extensions(),
+            Some("permessage-deflate, x-checksum")
+        );
+    }
+
+    #[test]
+    fn server_rejects_invalid_requests() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        use crate::upgrade::ServerUpgradeCodec;
+
+        let request = |request_line, upgrade| {
+            BytesMut::from(
+                format!(
+                    "{}\r\n\
+                     Host: server.example.com\r\n\
+                     Upgrade: {}\r\n\
+                     Connection: Upgrade\r\n\
+                     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                     Sec-WebSocket-Version: 13\r\n\
+                     \r\n",
+                    request_line, upgrade
+                )
+                .as_str(),
+            )
+        };
+
+        ServerUpgradeCodec
+            .decode(&mut request("GET / HTTP/1.1", "websocket"))
+            .unwrap()
+            .unwrap();
+
+        for (request_line, upgrade) in [
+            ("POST / HTTP/1.1", "websocket"),
+            ("GET / HTTP/1.0", "websocket"),
+            ("GET * HTTP/1.1", "websocket"),
+            ("GET / HTTP/1.1", "h2c"),
+            ("GET / HTTP/1.1\r\nBad Header", "websocket"),
+        ] {
+            ServerUpgradeCodec
+                .decode(&mut request(request_line, upgrade))
+                .expect_err(&format!("expected decoder to reject {:?}", request_line));
+        }
+    }
+
+    #[test]
+    fn server_encodes_upgrade_response() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        use crate::extension::tests::Checksum;
+        use crate::upgrade::{ClientRequest, ServerUpgradeCodec, UpgradeCodec, UpgradeResponse};
+
+        let request = ClientRequest::parse(|name| match name {
+            "Upgrade" => Some("websocket"),
+            "Connection" => Some("Upgrade"),
+            "Sec-WebSocket-Version" => Some("13"),
+            "Sec-WebSocket-Key" => Some("dGhlIHNhbXBsZSBub25jZQ=="),
+            _ => None,
+        })
+        .unwrap();
+
+        let response = UpgradeResponse::new(&request)
+            .with_protocol("chat".to_owned())
+            .with_extensions("x-checksum".to_owned())
+            .with_header("Set-Cookie".to_owned(), "session=1".to_owned());
+
+        let mut dst = BytesMut::new();
+        ServerUpgradeCodec.encode(&response, &mut dst).unwrap();
+        assert_eq!(
+            dst,
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             Sec-WebSocket-Protocol: chat\r\n\
+             Sec-WebSocket-Extensions: x-checksum\r\n\
+             Set-Cookie: session=1\r\n\
+             \r\n"
+        );
+
+        let mut client = UpgradeCodec::new("dGhlIHNhbXBsZSBub25jZQ==")
+            .with_protocol("chat".to_owned())
+            .with_extension(Box::new(Checksum));
+        let decoded = client.decode(&mut dst).unwrap().unwrap();
+        assert_eq!(decoded.protocol(), Some("chat"));
+        assert_eq!(client.extensions().len(), 1);
+
+        for (name, value) in [("Set-Cookie", "a=1\r\nX-Injected: 1"), ("Bad Name", "1"), ("", "1")] {
+            ServerUpgradeCodec
+                .encode(
+                    UpgradeResponse::new(&request).with_header(name.to_owned(), value.to_owned()),
+                    &mut BytesMut::new(),
+                )
+                .expect_err(&format!("expected encoder to reject header {:?}: {:?}", name, value));
+        }
     }
 }
@@ -0,0 +1,311 @@
+use alloc::string::String;
+use core::borrow::Borrow;
+use core::convert::TryFrom;
+use core::ops::{Bound, Deref, RangeBounds};
+use core::{fmt, result, str};
+
+use bytes::Bytes;
+
+use crate::Result;
+
+/// Checks that `data` is valid UTF-8.
+///
+/// With the `simd-utf8` feature, valid text is checked using SIMD instructions. Invalid text is checked again by the
+/// standard library, which describes where the error is.
+pub(crate) fn validate(data: &[u8]) -> result::Result<(), str::Utf8Error> {
+    #[cfg(feature = "simd-utf8")]
+    if simdutf8::basic::from_utf8(data).is_ok() {
+        return Ok(());
+    }
+
+    str::from_utf8(data).map(drop)
+}
+
+/// Validates UTF-8 text that arrives in chunks, where a chunk may end partway through a character.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Utf8Validator {
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+// Returns the length of the character that starts with `b`, which must be the first byte of a valid multi-byte
+// sequence.
+fn char_len(b: u8) -> usize {
+    match b {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        _ => 4,
+    }
+}
+
+impl Utf8Validator {
+    /// Validates the next chunk of text.
+    ///
+    /// Fails as soon as the text seen so far can't be the start of valid UTF-8.
+    pub(crate) fn feed(&mut self, mut data: &[u8]) -> Result<()> {
+        if self.partial_len > 0 {
+            // Complete the character left over from the previous chunk
+            let start = self.partial_len;
+            let len = (char_len(self.partial[0]) - start).min(data.len());
+            self.partial[start..start + len].copy_from_slice(&data[..len]);
+            self.partial_len += len;
+            data = &data[len..];
+
+            match str::from_utf8(&self.partial[..self.partial_len]) {
+                Ok(_) => self.partial_len = 0,
+                Err(e) if e.error_len().is_none() => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match validate(data) {
+            Ok(()) => Ok(()),
+            Err(e) if e.error_len().is_none() => {
+                let rest = &data[e.valid_up_to()..];
+                self.partial[..rest.len()].copy_from_slice(rest);
+                self.partial_len = rest.len();
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Checks that the text didn't end partway through a character, and resets the validator for the next message.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        let partial_len = self.partial_len;
+        self.partial_len = 0;
+        if partial_len > 0 {
+            // The leftover bytes are the start of a character, so they fail validation on their own
+            return Err(str::from_utf8(&self.partial[..partial_len]).unwrap_err().into());
+        }
+
+        Ok(())
+    }
+}
+
+/// A [`Bytes`] buffer that is known to hold valid UTF-8 text.
+///
+/// Cloning and slicing a `Utf8Bytes` share the underlying buffer instead of copying the text, and the text is never
+/// validated again. Text messages hold their payload this way; see [`Message::to_text`](crate::Message::to_text).
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Utf8Bytes(Bytes);
+
+impl Utf8Bytes {
+    /// Creates a `Utf8Bytes` that points to a static string, without allocating.
+    #[must_use]
+    pub const fn from_static(s: &'static str) -> Self {
+        Self(Bytes::from_static(s.as_bytes()))
+    }
+
+    // `data` must be valid UTF-8
+    pub(crate) fn from_bytes_unchecked(data: Bytes) -> Self {
+        debug_assert!(str::from_utf8(&data).is_ok());
+        Self(data)
+    }
+
+    /// Returns the text as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        // The contents were validated when the `Utf8Bytes` was created
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    /// Returns a reference to the underlying bytes.
+    #[must_use]
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+
+    /// Consumes the `Utf8Bytes`, returning the underlying bytes.
+    #[must_use]
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    /// Returns a slice of the text that shares the same buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either end of `range` is out of bounds or does not fall on a character boundary, as indexing a
+    /// `str` does.
+    #[must_use]
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Self {
+        let begin = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.len(),
+        };
+
+        assert!(
+            begin <= end && self.is_char_boundary(begin) && self.is_char_boundary(end),
+            "range {}..{} is not on character boundaries of text of length {}",
+            begin,
+            end,
+            self.len()
+        );
+
+        Self(self.0.slice(begin..end))
+    }
+}
+
+impl Deref for Utf8Bytes {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Utf8Bytes {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<[u8]> for Utf8Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Utf8Bytes {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl From<&'static str> for Utf8Bytes {
+    fn from(s: &'static str) -> Self {
+        Self::from_static(s)
+    }
+}
+
+impl From<String> for Utf8Bytes {
+    fn from(s: String) -> Self {
+        Self(s.into())
+    }
+}
+
+impl From<Utf8Bytes> for Bytes {
+    fn from(s: Utf8Bytes) -> Self {
+        s.0
+    }
+}
+
+impl TryFrom<Bytes> for Utf8Bytes {
+    type Error = str::Utf8Error;
+
+    fn try_from(data: Bytes) -> result::Result<Self, str::Utf8Error> {
+        validate(&data)?;
+        Ok(Self(data))
+    }
+}
+
+impl PartialEq<str> for Utf8Bytes {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Utf8Bytes {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Utf8Bytes {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use crate::utf8::{self, Utf8Bytes, Utf8Validator};
+
+    #[test]
+    fn describes_where_invalid_text_starts() {
+        let mut data = "caf\u{e9} ".repeat(20).into_bytes();
+        data.push(0xff);
+        let err = utf8::validate(&data).unwrap_err();
+        assert_eq!(err.valid_up_to(), data.len() - 1);
+        assert_eq!(err.error_len(), Some(1));
+    }
+
+    #[quickcheck]
+    #[allow(clippy::needless_pass_by_value)] // clippy wants &str, but quickcheck can only give us String
+    fn accepts_valid_text_split_anywhere(s: String, split: usize) {
+        let bytes = s.as_bytes();
+        let split = split % (bytes.len() + 1);
+        let mut validator = Utf8Validator::default();
+        validator.feed(&bytes[..split]).unwrap();
+        validator.feed(&bytes[split..]).unwrap();
+        validator.finish().unwrap();
+    }
+
+    #[test]
+    fn accepts_character_split_across_three_chunks() {
+        let bytes = "\u{1f600}".as_bytes();
+        let mut validator = Utf8Validator::default();
+        validator.feed(&bytes[..1]).unwrap();
+        validator.feed(&bytes[1..3]).unwrap();
+        validator.feed(&bytes[3..]).unwrap();
+        validator.finish().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_text_before_the_end() {
+        let mut validator = Utf8Validator::default();
+        validator.feed(b"abc\xe2\x82").unwrap();
+        validator.feed(b"x").unwrap_err();
+    }
+
+    #[test]
+    fn rejects_incomplete_character_at_the_end() {
+        let mut validator = Utf8Validator::default();
+        validator.feed(b"abc\xe2\x82").unwrap();
+        validator.finish().unwrap_err();
+    }
+
+    #[test]
+    fn slices_text_on_character_boundaries() {
+        let text = Utf8Bytes::from_static("caf\u{e9} au lait");
+        assert_eq!(text.slice(..5), "caf\u{e9}");
+        assert_eq!(text.slice(6..=7), "au");
+        assert_eq!(text.slice(9..).as_bytes().as_ptr(), text[9..].as_ptr());
+        assert!(std::panic::catch_unwind(|| text.slice(..4)).is_err());
+        assert!(std::panic::catch_unwind(|| text.slice(..20)).is_err());
+    }
+
+    #[test]
+    fn validates_bytes() {
+        assert_eq!(
+            Utf8Bytes::try_from(Bytes::from_static(b"caf\xc3\xa9")).unwrap(),
+            "caf\u{e9}"
+        );
+        let err = Utf8Bytes::try_from(Bytes::from_static(b"caf\xc3")).unwrap_err();
+        assert_eq!(err.valid_up_to(), 3);
+    }
+}
@@ -12,7 +12,9 @@ use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
 use structopt::StructOpt;
 use url::Url;
-use websocket_lite::{ClientBuilder, Message, Opcode, Result};
+use websocket_lite::{ClientBuilder, ManagedClient, Message};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "async-autobahn-client", about = "Client for the Autobahn fuzzing server")]
@@ -43,7 +45,7 @@ async fn main() -> Result<()> {
         );
 
         let builder = ClientBuilder::new(&url)?;
-        let mut stream_mut = builder.async_connect_insecure().await?;
+        let mut stream = ManagedClient::new(builder.async_connect_insecure().await?);
 
         {
             let stdout = io::stdout();
@@ -52,28 +54,9 @@ async fn main() -> Result<()> {
             stdout.flush()?;
         }
 
-        loop {
-            let (msg, mut stream) = stream_mut.into_future().await;
-
-            let msg = match msg {
-                Some(Ok(msg)) => msg,
-                Some(Err(_err)) => {
-                    let _ = stream.send(Message::close()).await;
-                    break;
-                }
-                None => {
-                    break;
-                }
-            };
-
-            match msg.opcode() {
-                Opcode::Text | Opcode::Binary => stream.send(msg).await?,
-                Opcode::Ping => stream.send(Message::pong(msg.into_data())).await?,
-                Opcode::Close => stream.send(Message::close()).await?,
-                Opcode::Pong => (),
-            }
-
-            stream_mut = stream;
+        // Pings and closes are answered by the managed client, which also ends the stream after a protocol error
+        while let Some(Ok(msg)) = stream.next().await {
+            stream.send(msg).await?;
         }
     }
 
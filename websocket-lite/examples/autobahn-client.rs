@@ -10,7 +10,9 @@ use std::io::Write;
 
 use structopt::StructOpt;
 use url::Url;
-use websocket_lite::{ClientBuilder, Message, Opcode, Result};
+use websocket_lite::{ClientBuilder, Message, Opcode};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "async-autobahn-client", about = "Client for the Autobahn fuzzing server")]
@@ -56,7 +58,7 @@ fn main() -> Result<()> {
 
                 Opcode::Ping => client.send(Message::pong(message.into_data()))?,
 
-                Opcode::Pong => (),
+                Opcode::Pong | Opcode::ReservedData(_) | Opcode::ReservedControl(_) => (),
             }
         }
     }
@@ -3,12 +3,12 @@
 
 use futures_util::sink::SinkExt;
 use futures_util::StreamExt;
-use websocket_lite::{Message, Opcode, Result};
+use websocket_lite::{ManagedClient, Message, Result};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let builder = websocket_lite::ClientBuilder::new("wss://gateway.discord.gg/?v=9&encoding=json")?;
-    let mut ws_stream = builder.async_connect().await?;
+    let mut ws_stream = ManagedClient::new(builder.async_connect().await?);
 
     let identify_payload = format!(
         "{{\"op\": 2, \"d\": {{\"token\": \"{}\", \"intents\": 32509, \"properties\": {{\"$os\": \"linux\", \"$browser\": \"rust-websocket-lite\", \"$device\": \"rust-websocket-lite\"}}}}}}",
@@ -17,21 +17,8 @@ async fn main() -> Result<()> {
     ws_stream.send(Message::text(identify_payload)).await?;
 
     while let Some(msg) = ws_stream.next().await {
-        if let Ok(m) = msg {
-            match m.opcode() {
-                Opcode::Text => {
-                    println!("{}", m.as_text().unwrap());
-                }
-                Opcode::Ping => ws_stream.send(Message::pong(m.into_data())).await?,
-                Opcode::Close => {
-                    let _ = ws_stream.send(Message::close()).await;
-                    break;
-                }
-                Opcode::Pong | Opcode::Binary => {}
-            }
-        } else {
-            let _ = ws_stream.send(Message::close()).await;
-            break;
+        if let Some(text) = msg?.as_text() {
+            println!("{}", text);
         }
     }
 
@@ -40,7 +40,7 @@ async fn main() -> Result<()> {
                 let _ = ws_stream.send(Message::close()).await;
                 break;
             }
-            Opcode::Pong => {}
+            Opcode::Pong | Opcode::ReservedData(_) | Opcode::ReservedControl(_) => {}
         }
     }
 
@@ -1,5 +1,6 @@
 use std::io;
 use std::io::Write;
+use std::num::ParseIntError;
 use std::time::Duration;
 
 use futures_util::future::FutureExt;
@@ -11,7 +12,7 @@ use tokio::time;
 use url::Url;
 use websocket_lite::{ClientBuilder, Message, Opcode, Result};
 
-fn parse_secs(s: &str) -> Result<Duration> {
+fn parse_secs(s: &str) -> std::result::Result<Duration, ParseIntError> {
     let n = s.parse()?;
     Ok(Duration::from_secs(n))
 }
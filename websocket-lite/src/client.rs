@@ -1,16 +1,20 @@
 use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpStream as StdTcpStream};
-use std::{fmt, mem, result, str};
+use std::{fmt, mem, str};
 
-use futures_util::StreamExt;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures_util::{SinkExt, StreamExt};
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream as TokioTcpStream;
 use tokio_util::codec::{Decoder, Framed};
 use url::Url;
-use websocket_codec::UpgradeCodec;
+#[cfg(feature = "deflate")]
+use websocket_codec::DeflateConfig;
+use websocket_codec::{Extension, HandshakeError, HandshakeRequest, UpgradeCodec};
 
 use crate::{
-    sync, AsyncClient, AsyncConnector, AsyncMaybeTlsStream, Client, Connector, MaybeTlsStream, MessageCodec, Result,
+    sync, AsyncClient, AsyncConnector, AsyncMaybeTlsStream, Client, Connector, Error, MaybeTlsStream, MessageCodec,
+    Result,
 };
 
 fn replace_codec<T, C1, C2>(framed: Framed<T, C1>, codec: C2) -> Framed<T, C2>
@@ -35,11 +39,24 @@ fn resolve(url: &Url) -> Result<SocketAddr> {
     url.socket_addrs(|| None)?
         .into_iter()
         .next()
-        .ok_or_else(|| "can't resolve host".to_owned().into())
+        .ok_or_else(|| Error::Url("can't resolve host".into()))
 }
 
-fn make_key(key: Option<[u8; 16]>, key_base64: &mut [u8; 24]) -> &str {
-    let key_bytes = key.unwrap_or_else(rand::random);
+fn make_key<'a>(
+    key: Option<[u8; 16]>,
+    rng: Option<&mut Box<dyn RngCore + Send>>,
+    key_base64: &'a mut [u8; 24],
+) -> &'a str {
+    let key_bytes = match (key, rng) {
+        (Some(key), _) => key,
+        (None, Some(rng)) => {
+            let mut key = [0; 16];
+            rng.fill_bytes(&mut key);
+            key
+        }
+        (None, None) => rand::random(),
+    };
+
     assert_eq!(
         24,
         base64::encode_config_slice(&key_bytes, base64::STANDARD, key_base64)
@@ -48,39 +65,25 @@ fn make_key(key: Option<[u8; 16]>, key_base64: &mut [u8; 24]) -> &str {
     str::from_utf8(key_base64).unwrap()
 }
 
-fn build_request(url: &Url, key: &str, headers: &[(String, String)]) -> String {
-    let mut s = String::new();
-    writeok!(s, "GET {path}", path = url.path());
-    if let Some(query) = url.query() {
-        writeok!(s, "?{query}", query = query);
-    }
-
-    s += " HTTP/1.1\r\n";
-
-    if let Some(host) = url.host() {
-        writeok!(s, "Host: {host}", host = host);
+fn build_request(url: &Url, headers: &[(String, String)]) -> HandshakeRequest {
+    let mut host = String::new();
+    if let Some(url_host) = url.host() {
+        writeok!(host, "{host}", host = url_host);
         if let Some(port) = url.port_or_known_default() {
-            writeok!(s, ":{port}", port = port);
+            writeok!(host, ":{port}", port = port);
         }
-
-        s += "\r\n";
     }
 
-    writeok!(
-        s,
-        "Upgrade: websocket\r\n\
-         Connection: Upgrade\r\n\
-         Sec-WebSocket-Key: {key}\r\n\
-         Sec-WebSocket-Version: 13\r\n",
-        key = key
-    );
-
-    for (name, value) in headers {
-        writeok!(s, "{name}: {value}\r\n", name = name, value = value);
+    let mut path = url.path().to_owned();
+    if let Some(query) = url.query() {
+        writeok!(path, "?{query}", query = query);
     }
 
-    writeok!(s, "\r\n");
-    s
+    headers
+        .iter()
+        .fold(HandshakeRequest::new(host, path), |request, (name, value)| {
+            request.with_header(name.clone(), value.clone())
+        })
 }
 
 /// Establishes a WebSocket connection.
@@ -91,7 +94,15 @@ pub struct ClientBuilder {
     connector: Option<Connector>,
     async_connector: Option<AsyncConnector>,
     key: Option<[u8; 16]>,
+    rng: Option<Box<dyn RngCore + Send>>,
     headers: Vec<(String, String)>,
+    max_frame_size: Option<usize>,
+    max_message_size: Option<usize>,
+    max_fragments: Option<usize>,
+    max_outgoing_frame_size: Option<usize>,
+    #[cfg(feature = "deflate")]
+    deflate: Option<DeflateConfig>,
+    extensions: Vec<Box<dyn Extension>>,
 }
 
 impl ClientBuilder {
@@ -99,9 +110,10 @@ impl ClientBuilder {
     ///
     /// # Errors
     ///
-    /// This method returns an `Err` result if URL parsing fails.
-    pub fn new(url: &str) -> result::Result<Self, url::ParseError> {
-        Ok(Self::from_url(Url::parse(url)?))
+    /// This method returns [`Error::Url`] if URL parsing fails.
+    pub fn new(url: &str) -> Result<Self> {
+        let url = Url::parse(url).map_err(|e| Error::Url(e.into()))?;
+        Ok(Self::from_url(url))
     }
 
     /// Creates a `ClientBuilder` that connects to a given WebSocket URL.
@@ -114,7 +126,15 @@ impl ClientBuilder {
             connector: None,
             async_connector: None,
             key: None,
+            rng: None,
             headers: Vec::new(),
+            max_frame_size: None,
+            max_message_size: None,
+            max_fragments: None,
+            max_outgoing_frame_size: None,
+            #[cfg(feature = "deflate")]
+            deflate: None,
+            extensions: Vec::new(),
         }
     }
 
@@ -135,6 +155,120 @@ impl ClientBuilder {
         self.headers.push((name, value));
     }
 
+    /// Limits the payload length of each frame received from the server.
+    ///
+    /// See [`MessageCodec::with_max_frame_size`].
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = Some(max_frame_size);
+    }
+
+    /// Limits the payload length of each message received from the server.
+    ///
+    /// See [`MessageCodec::with_max_message_size`].
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = Some(max_message_size);
+    }
+
+    /// Limits the number of frames that each message received from the server can be split into.
+    ///
+    /// See [`MessageCodec::with_max_fragments`].
+    pub fn set_max_fragments(&mut self, max_fragments: usize) {
+        self.max_fragments = Some(max_fragments);
+    }
+
+    /// Limits the payload length of each frame sent to the server, splitting longer messages into several frames.
+    ///
+    /// See [`MessageCodec::with_max_outgoing_frame_size`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_outgoing_frame_size` is zero.
+    pub fn set_max_outgoing_frame_size(&mut self, max_outgoing_frame_size: usize) {
+        assert!(
+            max_outgoing_frame_size > 0,
+            "maximum outgoing frame size must not be zero"
+        );
+        self.max_outgoing_frame_size = Some(max_outgoing_frame_size);
+    }
+
+    /// Offers the permessage-deflate compression extension to the server.
+    ///
+    /// Messages are compressed if the server accepts the offer. Decompressed messages from the server are limited to
+    /// the size set by [`DeflateConfig::with_max_decompressed_size`], which is 64 MiB by default, so that a small
+    /// compressed message cannot expand into a very large one.
+    #[cfg(feature = "deflate")]
+    pub fn set_deflate(&mut self, config: DeflateConfig) {
+        self.deflate = Some(config);
+    }
+
+    /// Offers an extension to the server.
+    ///
+    /// The extension transforms the frames sent and received if the server accepts the offer. See [`Extension`].
+    pub fn add_extension<E: Extension + 'static>(&mut self, extension: E) {
+        self.extensions.push(Box::new(extension));
+    }
+
+    /// Generates the `Sec-WebSocket-Key` header and the masks for frames sent to the server from `rng`, instead of
+    /// from the thread-local random number generator.
+    ///
+    /// This is intended for testing only, so that a seeded `rng` produces the same bytes on the wire each time. The
+    /// key and the masks are meant to be unpredictable, so don't use a predictable `rng` on a real connection.
+    ///
+    /// See [`MessageCodec::with_rng`].
+    pub fn set_rng<R: RngCore + Send + 'static>(&mut self, rng: R) {
+        self.rng = Some(Box::new(rng));
+    }
+
+    fn upgrade_codec(&self, key: &str) -> UpgradeCodec {
+        let mut upgrade_codec = UpgradeCodec::new(key);
+
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &self.deflate {
+            upgrade_codec = upgrade_codec.with_deflate(deflate.clone());
+        }
+
+        for extension in &self.extensions {
+            upgrade_codec = upgrade_codec.with_extension(extension.clone());
+        }
+
+        upgrade_codec
+    }
+
+    fn message_codec(&mut self, upgrade_codec: &UpgradeCodec) -> MessageCodec {
+        let mut codec = MessageCodec::client();
+
+        if let Some(rng) = self.rng.take() {
+            codec = codec.with_rng(rng);
+        }
+
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = upgrade_codec.deflate() {
+            codec = codec.with_deflate(deflate.clone());
+        }
+
+        for extension in upgrade_codec.extensions() {
+            codec = codec.with_extension(extension.clone());
+        }
+
+        if let Some(max_frame_size) = self.max_frame_size {
+            codec = codec.with_max_frame_size(max_frame_size);
+        }
+
+        if let Some(max_message_size) = self.max_message_size {
+            codec = codec.with_max_message_size(max_message_size);
+        }
+
+        if let Some(max_fragments) = self.max_fragments {
+            codec = codec.with_max_fragments(max_fragments);
+        }
+
+        if let Some(max_outgoing_frame_size) = self.max_outgoing_frame_size {
+            codec = codec.with_max_outgoing_frame_size(max_outgoing_frame_size);
+        }
+
+        codec
+    }
+
     /// Establishes a connection to the WebSocket server.
     ///
     /// `wss://...` URLs are not supported by this method. Use `async_connect` if you need to be able to handle
@@ -217,16 +351,16 @@ impl ClientBuilder {
     /// # Errors
     ///
     /// This method returns an `Err` result if writing or reading from the stream fails.
-    pub async fn async_connect_on<S: AsyncRead + AsyncWrite + Unpin>(self, mut stream: S) -> Result<AsyncClient<S>> {
+    pub async fn async_connect_on<S: AsyncRead + AsyncWrite + Unpin>(mut self, stream: S) -> Result<AsyncClient<S>> {
         let mut key_base64 = [0; 24];
-        let key = make_key(self.key, &mut key_base64);
-        let upgrade_codec = UpgradeCodec::new(key);
-        let request = build_request(&self.url, key, &self.headers);
-        AsyncWriteExt::write_all(&mut stream, request.as_bytes()).await?;
-
-        let (opt, framed) = upgrade_codec.framed(stream).into_future().await;
-        opt.ok_or_else(|| "no HTTP Upgrade response".to_owned())??;
-        Ok(replace_codec(framed, MessageCodec::client()))
+        let key = make_key(self.key, self.rng.as_mut(), &mut key_base64);
+        let mut framed = self.upgrade_codec(key).framed(stream);
+        framed.send(build_request(&self.url, &self.headers)).await?;
+
+        let (opt, framed) = framed.into_future().await;
+        opt.ok_or_else(|| HandshakeError::new("no HTTP Upgrade response"))??;
+        let codec = self.message_codec(framed.codec());
+        Ok(replace_codec(framed, codec))
     }
 
     /// Takes over an already established stream and uses it to send and receive WebSocket messages.
@@ -237,16 +371,17 @@ impl ClientBuilder {
     /// # Errors
     ///
     /// This method returns an `Err` result if writing or reading from the stream fails.
-    pub fn connect_on<S: Read + Write>(self, mut stream: S) -> Result<Client<S>> {
+    pub fn connect_on<S: Read + Write>(mut self, stream: S) -> Result<Client<S>> {
         let mut key_base64 = [0; 24];
-        let key = make_key(self.key, &mut key_base64);
-        let upgrade_codec = UpgradeCodec::new(key);
-        let request = build_request(&self.url, key, &self.headers);
-        Write::write_all(&mut stream, request.as_bytes())?;
-
-        let mut framed = sync::Framed::new(stream, upgrade_codec);
-        framed.receive()?.ok_or_else(|| "no HTTP Upgrade response".to_owned())?;
-        Ok(framed.replace_codec(MessageCodec::client()))
+        let key = make_key(self.key, self.rng.as_mut(), &mut key_base64);
+        let mut framed = sync::Framed::new(stream, self.upgrade_codec(key));
+        framed.send(build_request(&self.url, &self.headers))?;
+
+        framed
+            .receive()?
+            .ok_or_else(|| HandshakeError::new("no HTTP Upgrade response"))?;
+        let codec = self.message_codec(framed.codec());
+        Ok(framed.replace_codec(codec))
     }
 
     // Not pub - used by the tests
@@ -268,7 +403,7 @@ mod tests {
 
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-    use crate::ClientBuilder;
+    use crate::{ClientBuilder, CloseCode, Error, SizeLimit};
 
     type Result<T> = result::Result<T, crate::Error>;
 
@@ -350,7 +485,7 @@ mod tests {
         let mut output = Vec::new();
 
         ClientBuilder::new("ws://localhost:8000/stream?query")?
-            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==")?)
+            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==").unwrap())
             .async_connect_on(ReadWritePair(&mut input, &mut output))
             .await
             .unwrap();
@@ -365,10 +500,150 @@ mod tests {
         let mut output = Vec::new();
 
         ClientBuilder::new("ws://localhost:8000/stream?query")?
-            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==")?)
+            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==").unwrap())
             .connect_on(ReadWritePair(&mut input, &mut output))?;
 
         assert_eq!(REQUEST, str::from_utf8(&output)?);
         Ok(())
     }
+
+    #[test]
+    fn connect_on_uses_rng_for_key_and_masks() -> Result<()> {
+        use bytes::BytesMut;
+        use rand::rngs::mock::StepRng;
+        use rand::RngCore;
+        use tokio_util::codec::Encoder;
+
+        use crate::{Message, MessageCodec};
+
+        // Fills the key with the bytes of "the sample nonce", eight at a time, so that the response's accept key matches
+        let first = u64::from_le_bytes(*b"the samp");
+        let second = u64::from_le_bytes(*b"le nonce");
+        let rng = StepRng::new(first, second.wrapping_sub(first));
+
+        let mut output = Vec::new();
+        let mut builder = ClientBuilder::new("ws://localhost:8000/stream?query")?;
+        builder.set_rng(rng.clone());
+
+        let mut client = builder.connect_on(ReadWritePair(Cursor::new(RESPONSE), &mut output))?;
+        client.send(Message::text("Hello"))?;
+
+        // The masks continue from where the key left off
+        let mut rng = rng;
+        rng.next_u64();
+        rng.next_u64();
+
+        let mut expected = BytesMut::from(REQUEST);
+        MessageCodec::client()
+            .with_rng(rng)
+            .encode(Message::text("Hello"), &mut expected)?;
+
+        assert_eq!(&expected[..], &output[..]);
+        Ok(())
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn connect_on_negotiates_deflate() -> Result<()> {
+        use websocket_codec::DeflateConfig;
+
+        use crate::Message;
+
+        let mut input = RESPONSE
+            .replace(
+                "\r\n\r\n",
+                "\r\nSec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover\r\n\r\n",
+            )
+            .into_bytes();
+
+        // "Hello", compressed as in RFC 7692 section 7.2.3.1
+        input.extend_from_slice(&[0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+
+        let mut output = Vec::new();
+        let mut builder = ClientBuilder::new("ws://localhost:8000/stream?query")?
+            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==").unwrap());
+        builder.set_deflate(DeflateConfig::new());
+
+        let mut client = builder.connect_on(ReadWritePair(Cursor::new(input), &mut output))?;
+        assert_eq!(client.receive()?, Some(Message::text("Hello")));
+
+        let request = str::from_utf8(&output)?;
+        assert!(request.contains("\r\nSec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn connect_on_negotiates_extension() -> Result<()> {
+        use bytes::BytesMut;
+        use websocket_codec::protocol::FrameHeader;
+
+        use crate::{Extension, Message};
+
+        // Marks every frame with RSV3, leaving the payload unchanged
+        #[derive(Clone)]
+        struct Tag;
+
+        impl Extension for Tag {
+            fn name(&self) -> &str {
+                "x-tag"
+            }
+
+            fn rsv(&self) -> u8 {
+                0x10
+            }
+
+            fn encode(&mut self, _header: &FrameHeader, _data: &mut BytesMut) -> Result<u8> {
+                Ok(0x10)
+            }
+
+            fn decode(&mut self, _header: &FrameHeader, _data: &mut BytesMut) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut input = RESPONSE
+            .replace("\r\n\r\n", "\r\nSec-WebSocket-Extensions: x-tag\r\n\r\n")
+            .into_bytes();
+
+        input.extend_from_slice(&[0x91, 0x05]);
+        input.extend_from_slice(b"Hello");
+
+        let mut output = Vec::new();
+        let mut builder = ClientBuilder::new("ws://localhost:8000/stream?query")?
+            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==").unwrap());
+        builder.add_extension(Tag);
+
+        let mut client = builder.connect_on(ReadWritePair(Cursor::new(input), &mut output))?;
+        assert_eq!(client.receive()?, Some(Message::text("Hello")));
+
+        let request = str::from_utf8(&output)?;
+        assert!(request.contains("\r\nSec-WebSocket-Extensions: x-tag\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn connect_on_applies_size_limits() -> Result<()> {
+        let mut input = RESPONSE.as_bytes().to_vec();
+        input.extend_from_slice(&[0x82, 126, 0x10, 0x00]);
+
+        let mut output = Vec::new();
+        let mut builder = ClientBuilder::new("ws://localhost:8000/stream?query")?
+            .key(&base64::decode(b"dGhlIHNhbXBsZSBub25jZQ==").unwrap());
+        builder.set_max_frame_size(1024);
+
+        let mut client = builder.connect_on(ReadWritePair(Cursor::new(input), &mut output))?;
+        let err = client
+            .receive()
+            .expect_err("expected client to reject a frame over the limit");
+        let err = if let Error::Size(err) = err {
+            err
+        } else {
+            panic!("expected SizeLimitError, got {:?}", err)
+        };
+
+        assert_eq!(err.close_code(), CloseCode::Size);
+        assert_eq!(err.limit(), SizeLimit::FrameSize);
+        assert_eq!(err.actual(), 4096);
+        Ok(())
+    }
 }
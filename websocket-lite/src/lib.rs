@@ -11,23 +11,54 @@
 //!
 //! You can use this crate in both asynchronous (futures-based) and synchronous code.
 //! `native_tls` provides the TLS functionality for `wss://...` servers.
+//! Large messages can be received in parts, as their frames arrive, through an [`AsyncFragmentClient`], and sent in
+//! parts with [`send_from_async_read`] and [`send_from_read`]. A [`QueuedClient`] lets pings and other control messages
+//! overtake a large message that is being sent. Clients can send large binary messages with [`send_owned`], which
+//! masks the payload in place instead of copying it into the write buffer. A [`VectoredClient`] queues frame headers and
+//! payloads separately, and sends them with vectored writes. A [`ManagedClient`] answers pings and close frames
+//! automatically, so that applications only see text and binary messages.
+//! The `deflate` feature flag provides the permessage-deflate compression extension.
+//! The `simd-utf8` feature flag validates text messages using SIMD instructions, where the CPU supports them.
 //!
 //! This crate is fully conformant with the fuzzingserver module in the
 //! [Autobahn test suite](https://github.com/crossbario/autobahn-testsuite).
 
 mod client;
+mod managed;
+mod queue;
+mod reader;
 mod ssl;
 mod sync;
+mod vectored;
+mod writer;
 
 pub use crate::client::ClientBuilder;
+pub use crate::managed::ManagedClient;
+pub use crate::queue::QueuedClient;
+pub use crate::reader::MessageReader;
 pub use crate::ssl::{AsyncConnector, AsyncMaybeTlsStream, Connector, MaybeTlsStream};
+pub use crate::vectored::VectoredClient;
+pub use crate::writer::{send_from_async_read, send_from_read, send_owned, send_prepared};
 
-pub use websocket_codec::{CloseCode, CloseFrame, Error, Message, MessageCodec, Opcode, Result};
+pub use websocket_codec::{
+    CloseCode, CloseFrame, Connection, ConnectionState, Error, Extension, ExtensionClone, Fragment, FragmentCodec,
+    HandshakeError, Message, MessageCodec, Opcode, PreparedMessage, ProtocolError, Result, SizeLimit, SizeLimitError,
+    Utf8Bytes,
+};
+
+#[cfg(feature = "deflate")]
+pub use websocket_codec::DeflateConfig;
 
 use tokio_util::codec::Framed;
 
 /// Exposes a `Sink` and a `Stream` for sending and receiving WebSocket messages asynchronously.
 pub type AsyncClient<S> = Framed<S, MessageCodec>;
 
+/// Exposes a `Sink` for sending WebSocket messages, and a `Stream` for receiving them in parts as their frames arrive.
+///
+/// Convert an [`AsyncClient`] into an `AsyncFragmentClient` with `client.map_codec(FragmentCodec::from)`, and read
+/// the payload of a large message with a [`MessageReader`].
+pub type AsyncFragmentClient<S> = Framed<S, FragmentCodec>;
+
 /// Sends and receives WebSocket messages synchronously.
 pub type Client<S> = sync::Framed<S, MessageCodec>;
@@ -0,0 +1,274 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use websocket_codec::Connection;
+
+use crate::{AsyncClient, Error, Message, Opcode, Result};
+
+/// Sends and receives WebSocket messages, answering pings and close frames automatically.
+///
+/// The stream yields only text and binary messages. Pings are answered with pongs, and the peer's close frame is
+/// echoed back, as described in [`Connection`]. When the codec reports that the peer broke the protocol, the
+/// client sends a close frame with the matching close code before returning the error. The stream ends once the
+/// closing handshake is complete.
+///
+/// Replies are sent when the stream has no more messages ready, and before any message sent through the sink, so keep
+/// polling the stream, even while sending messages.
+pub struct ManagedClient<S> {
+    client: AsyncClient<S>,
+    connection: Connection,
+    // Set once the peer has closed the connection, or the connection has failed
+    done: bool,
+    // An error to return once the close frame that it caused has been sent
+    pending_error: Option<Error>,
+}
+
+impl<S> ManagedClient<S> {
+    /// Returns a `ManagedClient` that sends and receives messages through `client`.
+    #[must_use]
+    pub fn new(client: AsyncClient<S>) -> Self {
+        Self {
+            client,
+            connection: Connection::new(),
+            done: false,
+            pending_error: None,
+        }
+    }
+
+    /// Returns the state of the connection, including the peer's close frame once it has arrived.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Returns a reference to the underlying client.
+    pub fn get_ref(&self) -> &AsyncClient<S> {
+        &self.client
+    }
+
+    /// Returns a mutable reference to the underlying client.
+    ///
+    /// Messages sent and received directly through the underlying client bypass the connection's state machine.
+    pub fn get_mut(&mut self) -> &mut AsyncClient<S> {
+        &mut self.client
+    }
+
+    /// Returns the underlying client, discarding any replies that have not been sent.
+    pub fn into_inner(self) -> AsyncClient<S> {
+        self.client
+    }
+}
+
+impl<S: AsyncWrite + Unpin> ManagedClient<S> {
+    // Passes any pongs or close frames that the connection wants to send to the underlying client
+    fn poll_replies(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.connection.has_reply() {
+            ready!(Sink::<Message>::poll_ready(Pin::new(&mut self.client), cx))?;
+
+            if let Some(reply) = self.connection.next_reply() {
+                Pin::new(&mut self.client).start_send(reply)?;
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush_replies(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_replies(cx))?;
+        Sink::<Message>::poll_flush(Pin::new(&mut self.client), cx)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Sink<Message> for ManagedClient<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_replies(cx))?;
+        Sink::<Message>::poll_ready(Pin::new(&mut this.client), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<()> {
+        let this = self.get_mut();
+        this.connection.send(&item)?;
+        Pin::new(&mut this.client).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_flush_replies(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Sink::<Message>::poll_close(Pin::new(&mut self.client), cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for ManagedClient<S> {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Message>>> {
+        let this = self.get_mut();
+        loop {
+            if this.done {
+                let flushed = ready!(this.poll_flush_replies(cx));
+                if let Some(e) = this.pending_error.take() {
+                    return Poll::Ready(Some(Err(e)));
+                }
+
+                return Poll::Ready(flushed.err().map(Err));
+            }
+
+            // Replies wait until there is nothing more to read, so that a burst of pings gets a single pong
+            let result = match Pin::new(&mut this.client).poll_next(cx) {
+                Poll::Ready(Some(result)) => result,
+                Poll::Ready(None) => {
+                    this.done = true;
+                    continue;
+                }
+                Poll::Pending => {
+                    ready!(this.poll_flush_replies(cx))?;
+                    return Poll::Pending;
+                }
+            };
+
+            let is_close = matches!(&result, Ok(message) if message.opcode() == Opcode::Close);
+            match result.and_then(|message| this.connection.receive(message)) {
+                Ok(Some(message)) => return Poll::Ready(Some(Ok(message))),
+                Ok(None) => this.done = is_close,
+                Err(e) => {
+                    this.connection.fail(&e);
+                    this.pending_error = Some(e);
+                    this.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use bytes::BytesMut;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    use crate::{CloseCode, ManagedClient, Message, MessageCodec, Result};
+
+    // Reads from one buffer and writes to another
+    struct Peer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for Peer {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Peer {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.get_mut().output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn connect(
+        mut peer_codec: MessageCodec,
+        messages: Vec<Message>,
+        codec: MessageCodec,
+    ) -> Result<ManagedClient<Peer>> {
+        let mut input = BytesMut::new();
+        for message in messages {
+            peer_codec.encode(message, &mut input)?;
+        }
+
+        let peer = Peer {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        };
+
+        Ok(ManagedClient::new(Framed::new(peer, codec)))
+    }
+
+    fn sent(client: ManagedClient<Peer>, mut peer_codec: MessageCodec) -> Result<Vec<Message>> {
+        let mut output = BytesMut::from(&client.into_inner().into_inner().output[..]);
+        let mut messages = Vec::new();
+        while let Some(message) = peer_codec.decode(&mut output)? {
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    #[tokio::test]
+    async fn answers_pings_and_closes() -> Result<()> {
+        let messages = vec![
+            Message::ping("1"),
+            Message::text("hello"),
+            Message::ping("2"),
+            Message::ping("3"),
+            Message::close_with_reason(CloseCode::Away, "bye".to_owned()),
+            Message::text("ignored"),
+        ];
+
+        let mut client = connect(MessageCodec::server(), messages, MessageCodec::client())?;
+        assert_eq!(client.next().await.transpose()?, Some(Message::text("hello")));
+        client.send(Message::text("reply")).await?;
+        assert_eq!(client.next().await.transpose()?, None);
+        assert_eq!(client.connection().peer_close().unwrap().reason(), "bye");
+        client
+            .send(Message::text("late"))
+            .await
+            .expect_err("expected client to reject data after the close frame");
+
+        assert_eq!(
+            sent(client, MessageCodec::server())?,
+            vec![
+                Message::pong("1"),
+                Message::text("reply"),
+                Message::pong("3"),
+                Message::close_with_reason(CloseCode::Away, String::new()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closes_connection_on_protocol_error() -> Result<()> {
+        // A server must reject unmasked frames
+        let mut client = connect(
+            MessageCodec::server(),
+            vec![Message::text("hello")],
+            MessageCodec::server(),
+        )?;
+
+        let err = client
+            .next()
+            .await
+            .unwrap()
+            .expect_err("expected server to reject unmasked frame");
+        assert_eq!(err.close_code(), Some(CloseCode::Protocol));
+        assert!(client.next().await.is_none());
+
+        let sent = sent(client, MessageCodec::client())?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].as_close().unwrap().code(), CloseCode::Protocol);
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{AsyncClient, Error, Fragment, Message, Result};
+
+// The number of payload bytes in data messages waiting behind the current one, above which the sink stops accepting
+// messages until it has sent some of them
+const MAX_QUEUED_LEN: usize = 64 * 1024;
+
+/// Sends WebSocket messages through a queue that lets control frames overtake a large message.
+///
+/// Text and binary messages longer than the fragment size are sent as a sequence of frames, and each frame is
+/// written to the stream before the next one is encoded. A ping, pong or close message sent in the meantime is written
+/// after the current frame, instead of waiting for the rest of the large message. This keeps heartbeats timely during
+/// large uploads over slow connections.
+///
+/// The queue accepts messages without waiting until more than 64 KiB of data messages are queued behind the one being
+/// sent. Sending then waits while earlier frames are written, so that a producer using `send_all` or `forward` can't
+/// buffer a whole upload in memory. A control message sent while the queue is full waits too, then overtakes the
+/// queued data messages. Flushing the sink sends everything in the queue. Because the queue remembers how
+/// much of each message has been sent, it is safe to stop waiting for a flush, send a control message, then resume
+/// flushing:
+///
+/// ```no_run
+/// # async fn upload(mut client: websocket_lite::QueuedClient<tokio::net::TcpStream>) -> websocket_lite::Result<()> {
+/// use futures_util::SinkExt;
+/// use std::time::Duration;
+/// use websocket_lite::Message;
+///
+/// client.feed(Message::binary(vec![0; 100_000_000])).await?;
+///
+/// let mut interval = tokio::time::interval(Duration::from_secs(5));
+/// loop {
+///     tokio::select! {
+///         result = client.flush() => break result,
+///         _ = interval.tick() => client.feed(Message::ping("keepalive")).await?,
+///     }
+/// }
+/// # }
+/// ```
+///
+/// Messages longer than the fragment size are never compressed.
+pub struct QueuedClient<S> {
+    client: AsyncClient<S>,
+    fragment_size: usize,
+    control_messages: VecDeque<Message>,
+    data_messages: VecDeque<Message>,
+    // The total payload length of `data_messages`
+    queued_len: usize,
+    // The part of the current data message that has yet to be sent
+    remaining: Option<Bytes>,
+}
+
+impl<S> QueuedClient<S> {
+    /// Returns a `QueuedClient` that sends messages through `client`, in frames of up to `fragment_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fragment_size` is zero.
+    #[must_use]
+    pub fn new(client: AsyncClient<S>, fragment_size: usize) -> Self {
+        assert!(fragment_size > 0, "fragment size must not be zero");
+
+        Self {
+            client,
+            fragment_size,
+            control_messages: VecDeque::new(),
+            data_messages: VecDeque::new(),
+            queued_len: 0,
+            remaining: None,
+        }
+    }
+
+    /// Returns a reference to the underlying client.
+    pub fn get_ref(&self) -> &AsyncClient<S> {
+        &self.client
+    }
+
+    /// Returns a mutable reference to the underlying client.
+    ///
+    /// Messages sent directly through the underlying client bypass the queue.
+    pub fn get_mut(&mut self) -> &mut AsyncClient<S> {
+        &mut self.client
+    }
+
+    /// Returns the underlying client, discarding any messages that have not been sent.
+    pub fn into_inner(self) -> AsyncClient<S> {
+        self.client
+    }
+
+    // Returns the next frame to send, taking control messages ahead of the rest of the current data message
+    fn next_item(&mut self) -> Option<Item> {
+        if let Some(message) = self.control_messages.pop_front() {
+            return Some(Item::Message(message));
+        }
+
+        if let Some(remaining) = &mut self.remaining {
+            if remaining.is_empty() {
+                self.remaining = None;
+                return Some(Item::Fragment(Fragment::MessageEnd));
+            }
+
+            let len = remaining.len().min(self.fragment_size);
+            return Some(Item::Fragment(Fragment::MessageChunk(remaining.split_to(len))));
+        }
+
+        let message = self.data_messages.pop_front()?;
+        self.queued_len -= message.data().len();
+        if message.data().len() <= self.fragment_size {
+            return Some(Item::Message(message));
+        }
+
+        let opcode = message.opcode();
+        self.remaining = Some(message.into_data());
+        Some(Item::Fragment(Fragment::MessageStart { opcode }))
+    }
+}
+
+enum Item {
+    Message(Message),
+    Fragment(Fragment),
+}
+
+impl<S: AsyncWrite + Unpin> QueuedClient<S> {
+    // Sends the next frame, returning false once the queue is empty
+    fn poll_send_next(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        // Wait until the previous frame has been written, so that the next control message can go straight after it
+        ready!(Sink::<Message>::poll_flush(Pin::new(&mut self.client), cx))?;
+
+        let item = if let Some(item) = self.next_item() {
+            item
+        } else {
+            return Poll::Ready(Ok(false));
+        };
+
+        let client = Pin::new(&mut self.client);
+        match item {
+            Item::Message(message) => client.start_send(message)?,
+            Item::Fragment(fragment) => client.start_send(fragment)?,
+        }
+
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Sink<Message> for QueuedClient<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        while this.queued_len > MAX_QUEUED_LEN {
+            ready!(this.poll_send_next(cx))?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<()> {
+        let this = self.get_mut();
+        if item.opcode().is_control() {
+            this.control_messages.push_back(item);
+        } else {
+            this.queued_len += item.data().len();
+            this.data_messages.push_back(item);
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        while ready!(this.poll_send_next(cx))? {}
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Sink::<Message>::poll_close(Pin::new(&mut self.client), cx)
+    }
+}
+
+impl<S: AsyncRead + Unpin> Stream for QueuedClient<S> {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Message>>> {
+        Pin::new(&mut self.get_mut().client).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_util::{FutureExt, SinkExt, StreamExt};
+    use tokio::io::AsyncWrite;
+    use tokio_util::codec::Framed;
+
+    use crate::{Message, MessageCodec, QueuedClient, Result};
+
+    #[tokio::test]
+    async fn control_messages_overtake_data_messages() -> Result<()> {
+        let mut output = Vec::new();
+        let client = Framed::new(Cursor::new(&mut output), MessageCodec::server());
+        let mut client = QueuedClient::new(client, 4);
+        client.feed(Message::binary(&b"0123456789"[..])).await?;
+        client.feed(Message::text("abc")).await?;
+        client.feed(Message::ping("ping")).await?;
+        client.flush().await?;
+
+        assert_eq!(
+            output,
+            b"\x89\x04ping\x02\x040123\x00\x044567\x00\x0289\x80\x00\x81\x03abc".to_vec()
+        );
+
+        let mut client = Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(client.next().await.transpose()?, Some(Message::ping("ping")));
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Message::binary(&b"0123456789"[..]))
+        );
+        assert_eq!(client.next().await.transpose()?, Some(Message::text("abc")));
+        Ok(())
+    }
+
+    // Accepts no writes until it is unblocked
+    #[derive(Default)]
+    struct Blocked {
+        output: Vec<u8>,
+        unblocked: bool,
+    }
+
+    impl AsyncWrite for Blocked {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if !this.unblocked {
+                return Poll::Pending;
+            }
+
+            this.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_backpressure_to_data_messages() -> Result<()> {
+        let mut client = QueuedClient::new(Framed::new(Blocked::default(), MessageCodec::server()), 1024);
+        let data = Message::binary(vec![0x55; 40 * 1024]);
+        for _ in 0..3 {
+            client
+                .feed(data.clone())
+                .now_or_never()
+                .expect("expected the queue to accept the message")?;
+        }
+
+        assert!(
+            client.feed(data.clone()).now_or_never().is_none(),
+            "expected the queue to wait for earlier frames to be written"
+        );
+
+        client.get_mut().get_mut().unblocked = true;
+        client.feed(Message::ping("ping")).await?;
+        client.flush().await?;
+
+        let output = client.into_inner().into_inner().output;
+        // Queuing the ping waited for the first message to be sent, then the ping overtook the other two
+        let mut client = Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(client.next().await.transpose()?, Some(data.clone()));
+        assert_eq!(client.next().await.transpose()?, Some(Message::ping("ping")));
+        assert_eq!(client.next().await.transpose()?, Some(data.clone()));
+        assert_eq!(client.next().await.transpose()?, Some(data));
+
+        assert_eq!(client.next().await.transpose()?, None);
+        Ok(())
+    }
+}
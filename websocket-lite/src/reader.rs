@@ -0,0 +1,114 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::{ready, StreamExt};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{AsyncFragmentClient, Error, Fragment, Message};
+
+/// Exposes the payload of one incoming message as an `AsyncRead`.
+///
+/// Create a `MessageReader` after receiving [`Fragment::MessageStart`] from an [`AsyncFragmentClient`]. The reader
+/// returns the message's chunks as they arrive, and reaches end-of-file once the message's final frame has been
+/// received. This allows a large message to be copied to disk, or into a parser, without holding the whole message in
+/// memory.
+///
+/// Control frames that arrive while the message is being read are kept, and can be retrieved with
+/// [`MessageReader::take_control_messages`].
+pub struct MessageReader<'a, S> {
+    client: &'a mut AsyncFragmentClient<S>,
+    chunk: Bytes,
+    done: bool,
+    control_messages: Vec<Message>,
+}
+
+impl<'a, S> MessageReader<'a, S> {
+    /// Returns a reader for the message whose [`Fragment::MessageStart`] was the last item received from `client`.
+    pub fn new(client: &'a mut AsyncFragmentClient<S>) -> Self {
+        Self {
+            client,
+            chunk: Bytes::new(),
+            done: false,
+            control_messages: Vec::new(),
+        }
+    }
+
+    /// Returns the control frames received while reading the message, such as pings and close frames.
+    pub fn take_control_messages(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.control_messages)
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MessageReader<'_, S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.chunk.is_empty() {
+                let len = this.chunk.len().min(buf.remaining());
+                buf.put_slice(&this.chunk[..len]);
+                this.chunk.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.done {
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(this.client.poll_next_unpin(cx)) {
+                Some(Ok(Fragment::MessageChunk(chunk))) => this.chunk = chunk,
+                Some(Ok(Fragment::MessageEnd)) => this.done = true,
+                Some(Ok(Fragment::Control(message))) => this.control_messages.push(message),
+                Some(Ok(Fragment::MessageStart { .. })) => {
+                    return Poll::Ready(Err(invalid_data("message started before the previous message ended")));
+                }
+                Some(Ok(Fragment::MessageAbort)) => {
+                    return Poll::Ready(Err(invalid_data("message was abandoned before it ended")));
+                }
+                Some(Err(Error::Io(e))) => return Poll::Ready(Err(e)),
+                Some(Err(e)) => return Poll::Ready(Err(invalid_data(e))),
+                None => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncReadExt;
+    use tokio_util::codec::Framed;
+
+    use crate::{Fragment, FragmentCodec, Message, MessageReader, Opcode, Result};
+
+    #[tokio::test]
+    async fn reads_message_across_frames() -> Result<()> {
+        let input: &[u8] = b"\x01\x03abc\x89\x04ping\x80\x02de\x82\x01!";
+        let mut client = Framed::new(input, FragmentCodec::client());
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Fragment::MessageStart { opcode: Opcode::Text })
+        );
+
+        let mut reader = MessageReader::new(&mut client);
+        let mut text = String::new();
+        reader.read_to_string(&mut text).await?;
+        assert_eq!(text, "abcde");
+        assert_eq!(reader.take_control_messages(), vec![Message::ping("ping")]);
+
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Fragment::MessageStart { opcode: Opcode::Binary })
+        );
+
+        Ok(())
+    }
+}
@@ -10,6 +10,8 @@ use std::{fmt, io};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream as TokioTcpStream;
 
+#[cfg(feature = "__ssl")]
+use crate::Error;
 use crate::Result;
 
 #[cfg(all(feature = "ssl-native-tls", feature = "__ssl-rustls"))]
@@ -17,6 +19,14 @@ compile_error!("Only one TLS backend may be enabled at once");
 #[cfg(all(feature = "ssl-rustls-webpki-roots", feature = "ssl-rustls-native-roots"))]
 compile_error!("Only one of ssl-rustls-webpki-roots and ssl-rustls-native-roots may be enabled at once");
 
+#[cfg(feature = "__ssl")]
+fn tls_error<E>(error: E) -> Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    Error::Tls(error.into())
+}
+
 /// A reusable TLS connector for wrapping streams.
 #[derive(Clone)]
 pub enum Connector {
@@ -191,7 +201,7 @@ impl Connector {
         }
         #[cfg(feature = "ssl-native-tls")]
         {
-            Ok(Self::NativeTls(native_tls::TlsConnector::new()?))
+            Ok(Self::NativeTls(native_tls::TlsConnector::new().map_err(tls_error)?))
         }
         #[cfg(feature = "ssl-rustls-webpki-roots")]
         {
@@ -206,7 +216,7 @@ impl Connector {
             let mut config = rustls::ClientConfig::new();
             config.root_store = match rustls_native_certs::load_native_certs() {
                 Ok(store) | Err((Some(store), _)) => store,
-                Err((None, err)) => return Err(err.into()),
+                Err((None, err)) => return Err(tls_error(err)),
             };
             assert!(!config.root_store.is_empty(), "no CA certificates found");
             Ok(Self::Rustls(Arc::new(config)))
@@ -220,11 +230,15 @@ impl Connector {
         let inner = match self {
             Self::Plain => MaybeTlsStreamInner::Plain(stream),
             #[cfg(feature = "ssl-native-tls")]
-            Self::NativeTls(connector) => MaybeTlsStreamInner::NativeTls(connector.connect(domain, stream)?),
+            Self::NativeTls(connector) => {
+                MaybeTlsStreamInner::NativeTls(connector.connect(domain, stream).map_err(tls_error)?)
+            }
             #[cfg(feature = "__ssl-rustls")]
             Self::Rustls(client_config) => {
-                let session =
-                    rustls::ClientSession::new(&client_config, webpki::DNSNameRef::try_from_ascii_str(domain)?);
+                let session = rustls::ClientSession::new(
+                    &client_config,
+                    webpki::DNSNameRef::try_from_ascii_str(domain).map_err(tls_error)?,
+                );
                 MaybeTlsStreamInner::Rustls(rustls::StreamOwned::new(session, stream))
             }
         };
@@ -251,7 +265,9 @@ impl AsyncConnector {
         }
         #[cfg(feature = "ssl-native-tls")]
         {
-            Ok(Self::NativeTls(native_tls::TlsConnector::new()?.into()))
+            Ok(Self::NativeTls(
+                native_tls::TlsConnector::new().map_err(tls_error)?.into(),
+            ))
         }
         #[cfg(feature = "ssl-rustls-webpki-roots")]
         {
@@ -267,7 +283,7 @@ impl AsyncConnector {
             let mut config = rustls::ClientConfig::new();
             config.root_store = match rustls_native_certs::load_native_certs() {
                 Ok(store) | Err((Some(store), _)) => store,
-                Err((None, err)) => return Err(err.into()),
+                Err((None, err)) => return Err(tls_error(err)),
             };
             assert!(!config.root_store.is_empty(), "no CA certificates found");
             let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
@@ -282,11 +298,16 @@ impl AsyncConnector {
         let inner = match self {
             Self::Plain => AsyncMaybeTlsStreamInner::Plain(stream),
             #[cfg(feature = "ssl-native-tls")]
-            Self::NativeTls(connector) => AsyncMaybeTlsStreamInner::NativeTls(connector.connect(domain, stream).await?),
+            Self::NativeTls(connector) => {
+                AsyncMaybeTlsStreamInner::NativeTls(connector.connect(domain, stream).await.map_err(tls_error)?)
+            }
             #[cfg(feature = "__ssl-rustls")]
             Self::Rustls(connector) => AsyncMaybeTlsStreamInner::Rustls(
                 connector
-                    .connect(webpki::DNSNameRef::try_from_ascii_str(domain)?, stream)
+                    .connect(
+                        webpki::DNSNameRef::try_from_ascii_str(domain).map_err(tls_error)?,
+                        stream,
+                    )
                     .await?,
             ),
         };
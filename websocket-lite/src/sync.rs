@@ -1,7 +1,8 @@
-use std::io::{Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 
 use bytes::{BufMut, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
+use websocket_codec::{Message, MessageCodec};
 
 pub struct Framed<S, C> {
     stream: S,
@@ -20,6 +21,10 @@ impl<S, C> Framed<S, C> {
         }
     }
 
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
     pub fn replace_codec<D>(self, codec: D) -> Framed<S, D> {
         Framed {
             stream: self.stream,
@@ -42,6 +47,41 @@ impl<S: Write, C> Framed<S, C> {
     }
 }
 
+impl<S: Write> Framed<S, MessageCodec> {
+    /// Sends a message, writing its payload straight to the stream instead of copying it into the write buffer.
+    ///
+    /// The frame header and the payload are written together with vectored writes. See
+    /// [`MessageCodec::encode_owned`] for which messages avoid the copy.
+    pub fn send_owned(&mut self, item: Message) -> websocket_codec::Result<()> {
+        self.write_buf.truncate(0);
+        let payload = self.codec.encode_owned(item, &mut self.write_buf)?;
+
+        let mut header = &self.write_buf[..];
+        let mut payload = &payload[..];
+        while !header.is_empty() {
+            let n = match self
+                .stream
+                .write_vectored(&[IoSlice::new(header), IoSlice::new(payload)])
+            {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if n < header.len() {
+                header = &header[n..];
+            } else {
+                payload = &payload[n - header.len()..];
+                header = &[];
+            }
+        }
+
+        self.stream.write_all(payload)?;
+        Ok(())
+    }
+}
+
 impl<S: Read, C: Decoder> Framed<S, C> {
     pub fn receive(&mut self) -> Result<Option<C::Item>, C::Error> {
         loop {
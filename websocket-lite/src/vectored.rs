@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{AsyncClient, Error, Message, Result};
+
+// The most buffers passed to a single vectored write; Linux limits writev to 1024
+const MAX_SLICES: usize = 64;
+
+// The number of queued bytes above which the sink writes out the queue before accepting another message
+const MAX_QUEUED_LEN: usize = 64 * 1024;
+
+// Frame headers are written into blocks of this size, so that each header doesn't take an allocation of its own
+const HEADER_BLOCK_LEN: usize = 4 * 1024;
+
+// The longest frame header: 2 bytes, then an 8-byte length and a 4-byte mask
+const MAX_HEADER_LEN: usize = 14;
+
+/// Sends WebSocket messages without copying their payloads into a write buffer.
+///
+/// The frame headers and the payloads are queued separately, then written together with vectored writes. A payload
+/// held in [`Bytes`] is written from its original buffer, so a server can send one message to many clients while
+/// holding a single copy of the payload in memory. Clients, which mask their messages, avoid the copy for messages
+/// that hold the only reference to their buffer, as described in
+/// [`MessageCodec::encode_owned`](crate::MessageCodec::encode_owned).
+///
+/// The queue accepts messages without waiting until it holds more than 64 KiB, counting the payloads it refers to.
+/// Sending then writes out the queue before accepting the next message. Flushing the sink sends everything in the
+/// queue. A synchronous
+/// [`Client`](crate::Client) can send in the same way through its `send_owned` method.
+pub struct VectoredClient<S> {
+    client: AsyncClient<S>,
+    header_buf: BytesMut,
+    // Frame headers and payloads that have yet to be written, in order
+    chunks: VecDeque<Bytes>,
+    // The total length of `chunks`
+    queued_len: usize,
+}
+
+impl<S> VectoredClient<S> {
+    /// Returns a `VectoredClient` that sends messages through `client`'s stream.
+    #[must_use]
+    pub fn new(client: AsyncClient<S>) -> Self {
+        Self {
+            client,
+            header_buf: BytesMut::new(),
+            chunks: VecDeque::new(),
+            queued_len: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying client.
+    pub fn get_ref(&self) -> &AsyncClient<S> {
+        &self.client
+    }
+
+    /// Returns a mutable reference to the underlying client.
+    ///
+    /// Messages sent directly through the underlying client bypass the queue.
+    pub fn get_mut(&mut self) -> &mut AsyncClient<S> {
+        &mut self.client
+    }
+
+    /// Returns the underlying client, discarding any messages that have not been sent.
+    pub fn into_inner(self) -> AsyncClient<S> {
+        self.client
+    }
+
+    // Drops the first `n` bytes of the queue, once they have been written
+    fn advance(&mut self, mut n: usize) {
+        self.queued_len -= n;
+        while n > 0 {
+            let chunk = self
+                .chunks
+                .front_mut()
+                .expect("didn't expect to write more than the queue holds");
+            if n < chunk.len() {
+                chunk.advance(n);
+                return;
+            }
+
+            n -= chunk.len();
+            self.chunks.pop_front();
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Sink<Message> for VectoredClient<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.queued_len > MAX_QUEUED_LEN {
+            ready!(self.poll_flush(cx))?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<()> {
+        let this = self.get_mut();
+        if this.header_buf.capacity() < MAX_HEADER_LEN {
+            this.header_buf.reserve(HEADER_BLOCK_LEN);
+        }
+
+        let payload = this.client.codec_mut().encode_owned(item, &mut this.header_buf)?;
+        let header = this.header_buf.split().freeze();
+        this.queued_len += header.len() + payload.len();
+        this.chunks.push_back(header);
+        if !payload.is_empty() {
+            this.chunks.push_back(payload);
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+
+        // Anything sent directly through the underlying client goes first
+        ready!(Sink::<Message>::poll_flush(Pin::new(&mut this.client), cx))?;
+
+        while !this.chunks.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_SLICES];
+            let mut count = 0;
+            for (slice, chunk) in slices.iter_mut().zip(&this.chunks) {
+                *slice = IoSlice::new(chunk);
+                count += 1;
+            }
+
+            let n = ready!(Pin::new(this.client.get_mut()).poll_write_vectored(cx, &slices[..count]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+
+            this.advance(n);
+        }
+
+        Pin::new(this.client.get_mut()).poll_flush(cx).map_err(Error::from)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Sink::<Message>::poll_close(Pin::new(&mut self.client), cx)
+    }
+}
+
+impl<S: AsyncRead + Unpin> Stream for VectoredClient<S> {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Message>>> {
+        Pin::new(&mut self.get_mut().client).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use bytes::{Bytes, BytesMut};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::AsyncWrite;
+    use tokio_util::codec::{Encoder, Framed};
+
+    use crate::{Message, MessageCodec, Result, VectoredClient};
+
+    // Accepts at most three bytes per write, so that writes end partway through the queued buffers
+    struct Trickle(Vec<u8>);
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(3);
+            self.get_mut().0.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_same_bytes_as_message_codec() -> Result<()> {
+        let payload = Bytes::from(vec![0x55; 1000]);
+        let messages = vec![
+            Message::text("Hello"),
+            Message::binary(payload.clone()),
+            Message::ping("ping"),
+            Message::binary(payload),
+        ];
+
+        let mut expected = BytesMut::new();
+        for message in messages.clone() {
+            MessageCodec::server().encode(message, &mut expected)?;
+        }
+
+        let mut client = VectoredClient::new(Framed::new(Trickle(Vec::new()), MessageCodec::server()));
+        for message in messages {
+            client.feed(message).await?;
+        }
+
+        client.flush().await?;
+        assert_eq!(client.get_ref().get_ref().0, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn round_trips_masked_messages() -> Result<()> {
+        let mut client = VectoredClient::new(Framed::new(Trickle(Vec::new()), MessageCodec::client()));
+        client.feed(Message::text("Hello")).await?;
+        client
+            .feed(Message::binary_mut(BytesMut::from(&b", world"[..])))
+            .await?;
+        client.flush().await?;
+
+        let output = client.into_inner().into_inner().0;
+        let mut server = Framed::new(&output[..], MessageCodec::server());
+        assert_eq!(server.next().await.transpose()?, Some(Message::text("Hello")));
+        assert_eq!(server.next().await.transpose()?, Some(Message::binary(&b", world"[..])));
+        assert_eq!(server.next().await.transpose()?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_queue_before_accepting_more() -> Result<()> {
+        let payload = Bytes::from(vec![0x55; 70 * 1024]);
+        let mut client = VectoredClient::new(Framed::new(Trickle(Vec::new()), MessageCodec::server()));
+        client.feed(Message::binary(payload.clone())).await?;
+        assert!(client.get_ref().get_ref().0.is_empty());
+
+        client.feed(Message::text("Hello")).await?;
+        assert!(
+            client.get_ref().get_ref().0.len() > payload.len(),
+            "expected the first message to be written before the second was queued"
+        );
+
+        client.flush().await?;
+        let output = client.into_inner().into_inner().0;
+        let mut server = Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(server.next().await.transpose()?, Some(Message::binary(payload)));
+        assert_eq!(server.next().await.transpose()?, Some(Message::text("Hello")));
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use bytes::{BufMut, BytesMut};
+use futures_util::SinkExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Encoder, Framed};
+
+use crate::{sync, AsyncClient, Error, Fragment, Message, Opcode, PreparedMessage, Result};
+
+/// Sends a message, writing its payload straight to the stream instead of copying it into the client's write buffer.
+///
+/// A client masks the payload in place when the message holds the only reference to its buffer, as it does when
+/// created with [`Message::binary_mut`]. See [`MessageCodec::encode_owned`](crate::MessageCodec::encode_owned) for
+/// which messages avoid the copy. A synchronous [`Client`](crate::Client) can do the same through its `send_owned`
+/// method.
+///
+/// # Errors
+///
+/// This function fails if the message can't be encoded, or if writing to the stream fails.
+pub async fn send_owned<S: AsyncWrite + Unpin>(client: &mut AsyncClient<S>, item: Message) -> Result<()> {
+    // Write out anything already in the buffer, so that this message's frame follows it
+    SinkExt::<Message>::flush(client).await?;
+
+    // Encode the header into the client's empty write buffer, instead of allocating a buffer for each message
+    let mut header = mem::take(client.write_buffer_mut());
+    let result = match client.codec_mut().encode_owned(item, &mut header) {
+        Ok(payload) => write_frame(client.get_mut(), &header, &payload).await,
+        Err(e) => Err(e),
+    };
+
+    header.clear();
+    *client.write_buffer_mut() = header;
+    result
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, header: &[u8], payload: &[u8]) -> Result<()> {
+    stream.write_all(header).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Sends a message that was prepared once for many peers, writing its frame straight to the stream.
+///
+/// A server that doesn't compress the message writes the frame held by the [`PreparedMessage`], without encoding or
+/// copying it. See [`MessageCodec::encode_prepared`](crate::MessageCodec::encode_prepared) for how compressed and
+/// masked messages are sent. Any `AsyncClient` can also send a `PreparedMessage` through its `Sink` implementation,
+/// which copies the frame into the client's write buffer.
+///
+/// # Errors
+///
+/// This function fails if the message can't be encoded, or if writing to the stream fails.
+pub async fn send_prepared<S: AsyncWrite + Unpin>(client: &mut AsyncClient<S>, item: &PreparedMessage) -> Result<()> {
+    // Write out anything already in the buffer, so that this message's frame follows it
+    SinkExt::<Message>::flush(client).await?;
+
+    // Encode any header into the client's empty write buffer, instead of allocating a buffer for each connection
+    let mut header = mem::take(client.write_buffer_mut());
+    let result = match client.codec_mut().encode_prepared(item, &mut header) {
+        Ok(payload) => write_frame(client.get_mut(), &header, &payload).await,
+        Err(e) => Err(e),
+    };
+
+    header.clear();
+    *client.write_buffer_mut() = header;
+    result
+}
+
+/// Sends one message whose payload is read from `reader`, without holding the whole payload in memory.
+///
+/// The payload is sent in frames of up to `fragment_size` bytes, followed by an empty final frame once `reader`
+/// reaches end-of-file. The client can't send anything else until this function returns; to let pings and other
+/// control messages overtake a large message, send it through a [`QueuedClient`](crate::QueuedClient) instead.
+///
+/// # Errors
+///
+/// This function fails if reading from `reader` or writing to the stream fails. For [`Opcode::Text`] messages, it
+/// also fails if the payload is not valid UTF-8. The client can send other messages after an error, but any frames
+/// already sent leave the peer with an incomplete message, so the connection should normally be closed.
+///
+/// # Panics
+///
+/// Panics if `fragment_size` is zero.
+pub async fn send_from_async_read<S, C, R>(
+    client: &mut Framed<S, C>,
+    opcode: Opcode,
+    reader: R,
+    fragment_size: usize,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+    C: Encoder<Fragment, Error = Error>,
+    R: AsyncRead + Unpin,
+{
+    assert!(fragment_size > 0, "fragment size must not be zero");
+
+    client.feed(Fragment::MessageStart { opcode }).await?;
+
+    let result = send_chunks_from_async_read(client, reader, fragment_size).await;
+    if result.is_err() {
+        // Leave the client free to send other messages, such as a close frame
+        let _ = client.start_send_unpin(Fragment::MessageAbort);
+    }
+
+    result
+}
+
+async fn send_chunks_from_async_read<S, C, R>(
+    client: &mut Framed<S, C>,
+    mut reader: R,
+    fragment_size: usize,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+    C: Encoder<Fragment, Error = Error>,
+    R: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(fragment_size);
+    loop {
+        let mut eof = false;
+        while buf.len() < fragment_size {
+            let remaining = fragment_size - buf.len();
+            let mut limit = (&mut buf).limit(remaining);
+            if reader.read_buf(&mut limit).await? == 0 {
+                eof = true;
+                break;
+            }
+        }
+
+        if !buf.is_empty() {
+            client.feed(Fragment::MessageChunk(buf.split().freeze())).await?;
+            buf.reserve(fragment_size);
+        }
+
+        if eof {
+            break;
+        }
+    }
+
+    client.send(Fragment::MessageEnd).await
+}
+
+/// Sends one message whose payload is read from `reader`, without holding the whole payload in memory.
+///
+/// The payload is sent in frames of up to `fragment_size` bytes, followed by an empty final frame once `reader`
+/// reaches end-of-file. Reads that fail with [`io::ErrorKind::Interrupted`] are retried.
+///
+/// # Errors
+///
+/// This function fails if reading from `reader` or writing to the stream fails. For [`Opcode::Text`] messages, it
+/// also fails if the payload is not valid UTF-8. The client can send other messages after an error, but any frames
+/// already sent leave the peer with an incomplete message, so the connection should normally be closed.
+///
+/// # Panics
+///
+/// Panics if `fragment_size` is zero.
+pub fn send_from_read<S, C, R>(
+    client: &mut sync::Framed<S, C>,
+    opcode: Opcode,
+    reader: R,
+    fragment_size: usize,
+) -> Result<()>
+where
+    S: Write,
+    C: Encoder<Fragment, Error = Error>,
+    R: Read,
+{
+    assert!(fragment_size > 0, "fragment size must not be zero");
+
+    client.send(Fragment::MessageStart { opcode })?;
+
+    let result = send_chunks_from_read(client, reader, fragment_size);
+    if result.is_err() {
+        // Leave the client free to send other messages, such as a close frame
+        let _ = client.send(Fragment::MessageAbort);
+    }
+
+    result
+}
+
+fn send_chunks_from_read<S, C, R>(client: &mut sync::Framed<S, C>, mut reader: R, fragment_size: usize) -> Result<()>
+where
+    S: Write,
+    C: Encoder<Fragment, Error = Error>,
+    R: Read,
+{
+    let mut buf = BytesMut::new();
+    loop {
+        buf.resize(fragment_size, 0);
+
+        let mut len = 0;
+        while len < fragment_size {
+            match reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        buf.truncate(len);
+        if len > 0 {
+            client.send(Fragment::MessageChunk(buf.split().freeze()))?;
+        }
+
+        if len < fragment_size {
+            break;
+        }
+    }
+
+    client.send(Fragment::MessageEnd)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncRead, ReadBuf};
+    use tokio_util::codec::Framed;
+
+    use bytes::BytesMut;
+
+    use crate::writer::{send_from_async_read, send_from_read, send_owned, send_prepared};
+    use crate::{sync, Message, MessageCodec, Opcode, PreparedMessage, Result};
+
+    #[tokio::test]
+    async fn sends_message_from_async_read() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = Framed::new(Cursor::new(&mut output), MessageCodec::server());
+        send_from_async_read(&mut client, Opcode::Binary, &b"Hello, world"[..], 5).await?;
+        assert_eq!(
+            output,
+            b"\x02\x05Hello\x00\x05, wor\x00\x02ld\x80\x00".to_vec(),
+            "expected three fragments and an empty final frame"
+        );
+
+        let mut client = Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Message::binary(&b"Hello, world"[..]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sends_message_from_read() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = sync::Framed::new(&mut output, MessageCodec::server());
+        send_from_read(&mut client, Opcode::Text, &b"Hello, world"[..], 6)?;
+        send_from_read(&mut client, Opcode::Text, &b""[..], 6)?;
+
+        let mut client = sync::Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(client.receive()?, Some(Message::text("Hello, world")));
+        assert_eq!(client.receive()?, Some(Message::text("")));
+        assert_eq!(client.receive()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn retries_interrupted_reads() -> Result<()> {
+        struct Interrupting<'a>(&'a [u8], bool);
+
+        impl Read for Interrupting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 {
+                    Err(io::ErrorKind::Interrupted.into())
+                } else {
+                    self.0.read(buf)
+                }
+            }
+        }
+
+        let mut output = Vec::new();
+        let mut client = sync::Framed::new(&mut output, MessageCodec::server());
+        send_from_read(&mut client, Opcode::Text, Interrupting(b"Hello, world", false), 5)?;
+
+        let mut client = sync::Framed::new(&output[..], MessageCodec::client());
+        assert_eq!(client.receive()?, Some(Message::text("Hello, world")));
+        Ok(())
+    }
+
+    // Returns `data`, then fails
+    struct Failing<'a>(&'a [u8]);
+
+    impl Read for Failing<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                Err(io::ErrorKind::ConnectionReset.into())
+            } else {
+                self.0.read(buf)
+            }
+        }
+    }
+
+    impl AsyncRead for Failing<'_> {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            if this.0.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+
+            let len = this.0.len().min(buf.remaining());
+            buf.put_slice(&this.0[..len]);
+            this.0 = &this.0[len..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn sends_next_message_after_error() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = sync::Framed::new(&mut output, MessageCodec::server());
+        send_from_read(&mut client, Opcode::Text, &b"Hello, \xff"[..], 5)
+            .expect_err("expected invalid UTF-8 to be rejected");
+        client.send(Message::text("next"))?;
+
+        send_from_read(&mut client, Opcode::Binary, Failing(b"Hello, world"), 5)
+            .expect_err("expected the reader's error to be returned");
+        client.send(Message::text("last"))?;
+
+        // The peer sees the first frame of each abandoned message, then the next message
+        assert_eq!(
+            output,
+            b"\x01\x05Hello\x81\x04next\x02\x05Hello\x00\x05, wor\x81\x04last".to_vec()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_next_message_after_async_read_error() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = Framed::new(Cursor::new(&mut output), MessageCodec::server());
+        send_from_async_read(&mut client, Opcode::Binary, Failing(b"Hello, world"), 5)
+            .await
+            .expect_err("expected the reader's error to be returned");
+        client.send(Message::text("next")).await?;
+
+        assert_eq!(output, b"\x02\x05Hello\x00\x05, wor\x81\x04next".to_vec());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_prepared_message_to_each_client() -> Result<()> {
+        let prepared = PreparedMessage::new(Message::text("Hello, world"));
+        for codec in [MessageCodec::server(), MessageCodec::client()] {
+            let mut output = Vec::new();
+            let mut client = Framed::new(Cursor::new(&mut output), codec);
+            send_prepared(&mut client, &prepared).await?;
+            client.send(prepared.clone()).await?;
+
+            let mut client = Framed::new(&output[..], MessageCodec::with_masked_encode(false));
+            assert_eq!(client.next().await.transpose()?, Some(Message::text("Hello, world")));
+            assert_eq!(client.next().await.transpose()?, Some(Message::text("Hello, world")));
+            assert_eq!(client.next().await.transpose()?, None);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_owned_message() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = Framed::new(Cursor::new(&mut output), MessageCodec::client());
+        send_owned(&mut client, Message::ping("ping")).await?;
+        send_owned(&mut client, Message::binary_mut(BytesMut::from(&b"Hello, world"[..]))).await?;
+        send_owned(
+            &mut client,
+            Message::binary_mut(BytesMut::from(&vec![0; 1024 * 1024][..])),
+        )
+        .await?;
+        assert!(
+            client.write_buffer().capacity() < 64 * 1024,
+            "expected the write buffer not to grow to the size of the payload"
+        );
+
+        let mut client = Framed::new(&output[..], MessageCodec::server());
+        assert_eq!(client.next().await.transpose()?, Some(Message::ping("ping")));
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Message::binary(&b"Hello, world"[..]))
+        );
+        assert_eq!(
+            client.next().await.transpose()?,
+            Some(Message::binary(vec![0; 1024 * 1024]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sends_owned_message_synchronously() -> Result<()> {
+        let mut output = Vec::new();
+        let mut client = sync::Framed::new(&mut output, MessageCodec::client());
+        client.send(Message::text("Hello"))?;
+        client.send_owned(Message::binary_mut(BytesMut::from(&b", world"[..])))?;
+
+        let mut client = sync::Framed::new(&output[..], MessageCodec::server());
+        assert_eq!(client.receive()?, Some(Message::text("Hello")));
+        assert_eq!(client.receive()?, Some(Message::binary(&b", world"[..])));
+        Ok(())
+    }
+}